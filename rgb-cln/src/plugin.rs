@@ -2,15 +2,10 @@
 //!
 //! Author: Vincenzo Palazzo <vincenzopalazzo@member.fsf.org>
 use std::fmt;
-use std::fs;
-use std::io;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use json::Value;
-use lightning_signer::bitcoin as vlsbtc;
-use lightning_signer::signer::derive::KeyDerive;
-use lightning_signer::signer::derive::NativeKeyDerive;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json as json;
@@ -19,15 +14,23 @@ use clightningrpc::LightningRPC;
 use clightningrpc_plugin::error;
 use clightningrpc_plugin::errors::PluginError;
 use clightningrpc_plugin::{commands::RPCCommand, plugin::Plugin};
-use clightningrpc_plugin_macros::{plugin, rpc_method};
+use clightningrpc_plugin_macros::{notification, plugin, rpc_method};
 
 use rgb_common::anyhow;
-use rgb_common::bitcoin::bip32::ExtendedPrivKey;
+use rgb_common::config::RGBConfig;
+use rgb_common::rgb_storage::{FileStorage, RGBStorage};
 use rgb_common::RGBManager;
 
+mod channels;
+mod datastore;
+mod htlc;
 mod macros;
+mod paymentrpc;
+mod signer;
 mod walletrpc;
 
+use datastore::DatastoreStorage;
+
 #[derive(Clone, Debug)]
 pub(crate) struct State {
     /// The RGB Manager where we ask to do everything
@@ -69,16 +72,105 @@ pub fn build_plugin() -> anyhow::Result<Plugin<State>> {
     let mut plugin = plugin! {
         state: State::new(),
         dynamic: true,
-        notification: [ ],
+        notification: [
+            on_block_added,
+            on_channel_opened,
+            on_channel_state_changed,
+            on_invoice_payment,
+            on_sendpay_success,
+            on_sendpay_failure,
+            on_forward_event,
+        ],
         methods: [
             rgb_balance,
+            rgb_new_addr,
+            rgb_list_assets,
+            rgb_list_transfers,
+            rgb_list_channels,
+            rgb_refresh,
             rgb_fundchannel,
             rgb_issue_asset,
+            rgb_issue_asset_cfa,
+            rgb_issue_asset_uda,
             rgb_receive,
+            rgb_send,
             rgb_info,
+            rgb_invoice,
+            rgb_pay,
         ],
         hooks: [],
     };
+    plugin.add_opt(
+        "rgb-storage",
+        "string",
+        Some("file".to_owned()),
+        "Where to store the RGB channels information: `file` or `datastore`",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-allow-mainnet",
+        "bool",
+        Some("false".to_owned()),
+        "Allow the plugin to run on mainnet, where the funds are real",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-electrum-url",
+        "string",
+        None,
+        "Electrum server used by the RGB wallet, by default depends on the network",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-proxy-url",
+        "string",
+        None,
+        "RGB proxy used to exchange the consignments, by default depends on the network",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-min-confirmations",
+        "int",
+        None,
+        "Confirmations needed before an RGB allocation is spendable, by default depends on the network",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-max-allocations-per-utxo",
+        "int",
+        Some("11".to_owned()),
+        "Maximum number of RGB allocations that a single UTXO can hold",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-utxo-size-sat",
+        "int",
+        Some("32000".to_owned()),
+        "Size in sat of the UTXOs created to hold the RGB allocations",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-funding-fee-rate",
+        "string",
+        Some("1.1".to_owned()),
+        "Fee rate in sat/vB used to build the RGB funding transaction",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-refresh-interval",
+        "int",
+        Some("60".to_owned()),
+        "How often, in seconds, the pending RGB transfers are refreshed",
+        false,
+    );
+    plugin.add_opt(
+        "rgb-allow-issuance",
+        "bool",
+        Some("false".to_owned()),
+        "Allow the `issueasset` commands to mint new RGB assets",
+        false,
+    );
+    plugin.register_hook("htlc_accepted", None, None, OnHtlcAccepted);
     plugin.on_init(on_init);
 
     // FIXME: we disable this because it will create loop
@@ -91,6 +183,68 @@ pub fn rgb_balance(plugin: &mut Plugin<State>, requet: Value) -> Result<Value, P
     walletrpc::rgb_balance(plugin, requet)
 }
 
+#[rpc_method(
+    rpc_name = "rgbnewaddr",
+    description = "Return a new bitcoin address of the RGB wallet"
+)]
+fn rgb_new_addr(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    walletrpc::rgb_new_addr(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "issueassetcfa",
+    description = "Issue a new RGB Collectible Fungible Asset (RGB25)"
+)]
+fn rgb_issue_asset_cfa(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    walletrpc::rgb_issue_new_asset_cfa(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "issueassetuda",
+    description = "Issue a new RGB Unique Digital Asset (RGB21)"
+)]
+fn rgb_issue_asset_uda(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    walletrpc::rgb_issue_new_asset_uda(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "rgblistassets",
+    description = "List every RGB asset known by the wallet"
+)]
+fn rgb_list_assets(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    walletrpc::rgb_list_assets(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "rgblisttransfers",
+    description = "List the on-chain RGB transfers, filtered by `asset_id`, `direction` and `status`"
+)]
+fn rgb_list_transfers(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    walletrpc::rgb_list_transfers(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "rgblistchannels",
+    description = "List the RGB channels, filtered by `peer_id` and `asset_id`"
+)]
+fn rgb_list_channels(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    channels::rgb_list_channels(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "rgbrefresh",
+    description = "Advance the pending RGB transfers now"
+)]
+fn rgb_refresh(plugin: &mut Plugin<State>, _: Value) -> Result<Value, PluginError> {
+    macros::refuse_on_mainnet!(plugin, "rgbrefresh");
+    let changed = plugin
+        .state
+        .manager()
+        .refresh()
+        .map_err(|err| error!("{err}"))?;
+    Ok(json::json!({ "changed": changed }))
+}
+
 #[rpc_method(rpc_name = "fundrgbchannel", description = "Funding a RGB Channel")]
 fn rgb_fundchannel(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     walletrpc::fund_rgb_channel(plugin, request)
@@ -101,19 +255,42 @@ fn rgb_issue_asset(plugin: &mut Plugin<State>, request: Value) -> Result<Value,
     walletrpc::rgb_issue_new_assert(plugin, request)
 }
 
-#[rpc_method(rpc_name = "rgbreceive", description = "RGB Receive a asset on chain")]
+#[rpc_method(
+    rpc_name = "rgbreceive",
+    description = "RGB Receive a asset on chain, `mode` is `blinded` (default) or `witness`"
+)]
 fn rgb_receive(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     walletrpc::rgb_receive(plugin, request)
 }
 
-#[rpc_method(rpc_name = "rgbsendasset", description = "RGB Send a asset on chain")]
+#[rpc_method(
+    rpc_name = "rgbsendasset",
+    description = "RGB Send assets on chain to one or more recipients"
+)]
 fn rgb_send(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     walletrpc::rgb_send(plugin, request)
 }
 
+#[rpc_method(
+    rpc_name = "rgbinvoice",
+    description = "Create a Lightning invoice to receive a RGB asset"
+)]
+fn rgb_invoice(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    paymentrpc::rgb_invoice(plugin, request)
+}
+
+#[rpc_method(
+    rpc_name = "rgbpay",
+    description = "Pay a RGB invoice over RGB channels"
+)]
+fn rgb_pay(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    paymentrpc::rgb_pay(plugin, request)
+}
+
 // FIXME: this is just a test, we should remove it at some point
 #[rpc_method(rpc_name = "rgbinfo", description = "RGB Information")]
 fn rgb_info(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    macros::refuse_on_mainnet!(plugin, "rgbinfo");
     let info: Value = plugin
         .state
         .call("getinfo", json::json!({}))
@@ -121,45 +298,159 @@ fn rgb_info(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginE
     Ok(info)
 }
 
-fn read_secret(file: fs::File, network: &str) -> anyhow::Result<ExtendedPrivKey> {
-    let buffer = io::BufReader::new(file);
-    let network = vlsbtc::Network::from_str(network)?;
-    let hsmd_derive = NativeKeyDerive::new(network);
-    let xpriv = hsmd_derive.master_key(buffer.buffer()).to_string();
-    let xpriv = ExtendedPrivKey::from_str(&xpriv)?;
-    Ok(xpriv)
+/// The notification payload is wrapped inside an object
+/// with the name of the notification.
+pub(crate) fn parse_notification<T: DeserializeOwned>(
+    request: &Value,
+    name: &str,
+) -> anyhow::Result<T> {
+    let payload = request.get(name).unwrap_or(request).clone();
+    Ok(json::from_value(payload)?)
+}
+
+#[derive(Clone)]
+struct OnHtlcAccepted;
+
+impl RPCCommand<State> for OnHtlcAccepted {
+    fn call<'c>(&self, plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+        htlc::on_htlc_accepted(plugin, request)
+    }
+}
+
+#[notification(on = "block_added")]
+fn on_block_added(plugin: &mut Plugin<State>, _: &Value) {
+    // we may receive the notification before the init
+    if let Some(manager) = plugin.state.rgb_manager.as_ref() {
+        manager.trigger_refresh();
+    }
+}
+
+#[notification(on = "channel_opened")]
+fn on_channel_opened(plugin: &mut Plugin<State>, request: &Value) {
+    if let Err(err) = channels::on_channel_opened(plugin, request) {
+        log::error!("failing to handle `channel_opened`: {err}");
+    }
+}
+
+#[notification(on = "channel_state_changed")]
+fn on_channel_state_changed(plugin: &mut Plugin<State>, request: &Value) {
+    if let Err(err) = channels::on_channel_state_changed(plugin, request) {
+        log::error!("failing to handle `channel_state_changed`: {err}");
+    }
+}
+
+#[notification(on = "invoice_payment")]
+fn on_invoice_payment(plugin: &mut Plugin<State>, request: &Value) {
+    if let Err(err) = htlc::on_invoice_payment(plugin, request) {
+        log::error!("failing to handle `invoice_payment`: {err}");
+    }
+}
+
+#[notification(on = "sendpay_success")]
+fn on_sendpay_success(plugin: &mut Plugin<State>, request: &Value) {
+    if let Err(err) = htlc::on_sendpay_success(plugin, request) {
+        log::error!("failing to handle `sendpay_success`: {err}");
+    }
+}
+
+#[notification(on = "sendpay_failure")]
+fn on_sendpay_failure(plugin: &mut Plugin<State>, request: &Value) {
+    if let Err(err) = htlc::on_sendpay_failure(plugin, request) {
+        log::error!("failing to handle `sendpay_failure`: {err}");
+    }
+}
+
+#[notification(on = "forward_event")]
+fn on_forward_event(plugin: &mut Plugin<State>, request: &Value) {
+    if let Err(err) = htlc::on_forward_event(plugin, request) {
+        log::error!("failing to handle `forward_event`: {err}");
+    }
+}
+
+/// Build the RGB storage selected with the `rgb-storage` option.
+fn build_storage(
+    plugin: &Plugin<State>,
+    lightning_dir: &str,
+) -> anyhow::Result<Box<dyn RGBStorage>> {
+    let backend: String = plugin
+        .get_opt("rgb-storage")
+        .map_err(|err| anyhow::anyhow!("{err}"))?;
+    let storage: Box<dyn RGBStorage> = match backend.as_str() {
+        "file" => Box::new(FileStorage::new(lightning_dir)?),
+        "datastore" => Box::new(DatastoreStorage::new(plugin.state.clone())),
+        _ => anyhow::bail!("rgb storage `{backend}` not supported"),
+    };
+    Ok(storage)
+}
+
+fn build_config(plugin: &Plugin<State>) -> anyhow::Result<RGBConfig> {
+    let opt_err = |err: PluginError| anyhow::anyhow!("{err}");
+    let fee_rate: String = plugin.get_opt("rgb-funding-fee-rate").map_err(opt_err)?;
+    let config = RGBConfig {
+        electrum_url: plugin.get_opt("rgb-electrum-url").map_err(opt_err)?,
+        proxy_url: plugin.get_opt("rgb-proxy-url").map_err(opt_err)?,
+        min_confirmations: plugin.get_opt("rgb-min-confirmations").map_err(opt_err)?,
+        max_allocations_per_utxo: plugin
+            .get_opt("rgb-max-allocations-per-utxo")
+            .map_err(opt_err)?,
+        utxo_size_sat: plugin.get_opt("rgb-utxo-size-sat").map_err(opt_err)?,
+        funding_fee_rate: fee_rate
+            .parse()
+            .map_err(|err| anyhow::anyhow!("invalid `rgb-funding-fee-rate`: {err}"))?,
+        refresh_interval_secs: plugin.get_opt("rgb-refresh-interval").map_err(opt_err)?,
+        allow_issuance: plugin.get_opt("rgb-allow-issuance").map_err(opt_err)?,
+        allow_mainnet: plugin.get_opt("rgb-allow-mainnet").map_err(opt_err)?,
+    };
+    Ok(config)
 }
 
 fn on_init(plugin: &mut Plugin<State>) -> json::Value {
     let config = plugin.configuration.clone().unwrap();
     let rpc_file = format!("{}/{}", config.lightning_dir, config.rpc_file);
-    let hsmd_file = format!("{}/hsm_secret", config.lightning_dir);
+    plugin.state.cln_rpc_path = Some(rpc_file);
 
-    let hsmd_file = fs::File::open(hsmd_file);
-    if let Err(err) = hsmd_file {
-        log::error!("failing open the hsmd file: {err}");
+    let master_xprv = signer::derive_master_key(&plugin.state, &config.network);
+    if let Err(err) = master_xprv {
+        log::error!("failing to derive the plugin master key: {err}");
         return json::json!({ "disable": format!("{err}") });
     }
     // SAFETY: we check if it is an error just before.
-    let hsmd_file = hsmd_file.unwrap();
+    let master_xprv = master_xprv.unwrap();
 
-    let hsmd_secret = read_secret(hsmd_file, &config.network);
-    if let Err(err) = hsmd_secret {
-        log::error!("failing reading hsmd secret: {err}");
+    let storage = build_storage(plugin, &config.lightning_dir);
+    if let Err(err) = storage {
+        log::error!("failing to init the rgb storage: {err}");
         return json::json!({ "disable": format!("{err}") });
     }
-    // SAFETY: we check if it is an error just error.
-    let master_xprv = hsmd_secret.unwrap();
+    // SAFETY: we check if it is an error just before.
+    let storage = storage.unwrap();
 
-    plugin.state.cln_rpc_path = Some(rpc_file);
+    let rgb_config = build_config(plugin);
+    if let Err(err) = rgb_config {
+        log::error!("failing to read the rgb configuration: {err}");
+        return json::json!({ "disable": format!("{err}") });
+    }
+    // SAFETY: we check if it is an error just before.
+    let rgb_config = rgb_config.unwrap();
 
-    let manager = RGBManager::init(&config.lightning_dir, &master_xprv, &config.network);
+    let manager = RGBManager::init(
+        &config.lightning_dir,
+        &master_xprv,
+        &config.network,
+        storage,
+        rgb_config,
+    );
     if let Err(err) = manager {
         log::error!("failing to init the rgb managar: {err}");
         return json::json!({ "disable": format!("{err}") });
     }
     // SAFETY: we check if it is an error just before.
-    let manager = manager.unwrap();
-    plugin.state.rgb_manager = Some(Arc::new(manager));
+    let manager = Arc::new(manager.unwrap());
+    let interval = Duration::from_secs(manager.config().refresh_interval_secs);
+    if let Err(err) = manager.start_refresh_task(interval) {
+        log::error!("failing to start the rgb refresh task: {err}");
+        return json::json!({ "disable": format!("{err}") });
+    }
+    plugin.state.rgb_manager = Some(manager);
     json::json!({})
 }
@@ -0,0 +1,271 @@
+//! RGB channels lifecycle driven by the Core Lightning notifications.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+use serde_json::Value;
+
+use clightningrpc_plugin::error;
+use clightningrpc_plugin::errors::PluginError;
+use clightningrpc_plugin::plugin::Plugin;
+
+use rgb_common::anyhow;
+use rgb_common::types::{RgbChannelState, RgbInfo};
+
+use crate::plugin::{parse_notification, State};
+
+#[derive(Debug, Deserialize)]
+struct ChannelOpened {
+    id: String,
+    funding_txid: String,
+    channel_ready: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelStateChanged {
+    channel_id: String,
+    short_channel_id: Option<String>,
+    new_state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListPeerChannelsResponse {
+    channels: Vec<PeerChannel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PeerChannel {
+    peer_id: Option<String>,
+    state: Option<String>,
+    /// `local` when we funded the channel, `remote` otherwise
+    opener: Option<String>,
+    channel_id: Option<String>,
+    short_channel_id: Option<String>,
+    funding_txid: Option<String>,
+    funding_outnum: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RgbListChannelsRequest {
+    peer_id: Option<String>,
+    asset_id: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RgbChannelStatus {
+    /// The funding transaction is not locked in yet
+    Pending,
+    Confirmed,
+}
+
+/// An RGB channel as cln and the RGB storage see it.
+#[derive(Debug, Serialize)]
+struct RgbChannel {
+    /// `None` when cln already forgot the channel
+    peer_id: Option<String>,
+    channel_id: String,
+    short_channel_id: Option<String>,
+    /// The cln channel state, e.g. `CHANNELD_NORMAL`
+    state: Option<String>,
+    status: RgbChannelStatus,
+    rgb_state: RgbChannelState,
+    contract_id: String,
+    ticker: Option<String>,
+    local_rgb_amount: u64,
+    remote_rgb_amount: u64,
+    /// `<txid>:<vout>` of the funding output
+    funding_outpoint: Option<String>,
+    /// Whether the fundee accepted the funding consignment,
+    /// `None` until it answers
+    funding_ack: Option<bool>,
+}
+
+/// Promote the channel info to confirmed, if we have a pending one.
+fn confirm_rgb_channel(
+    plugin: &mut Plugin<State>,
+    channel_id: &str,
+    short_channel_id: Option<String>,
+) -> anyhow::Result<()> {
+    let manager = plugin.state.manager();
+    if !manager.is_channel_rgb(channel_id, true)? {
+        return Ok(());
+    }
+    let info = manager.confirm_rgb_info(channel_id, short_channel_id)?;
+    log::info!("rgb channel `{channel_id}` is now open: {:?}", info);
+    Ok(())
+}
+
+fn update_rgb_channel_state(
+    plugin: &mut Plugin<State>,
+    channel_id: &str,
+    state: RgbChannelState,
+) -> anyhow::Result<()> {
+    let manager = plugin.state.manager();
+    if !manager.is_channel_rgb(channel_id, false)? {
+        return Ok(());
+    }
+    manager.update_rgb_channel_state(channel_id, state)
+}
+
+pub fn on_channel_opened(plugin: &mut Plugin<State>, request: &Value) -> anyhow::Result<()> {
+    let event: ChannelOpened = parse_notification(request, "channel_opened")?;
+    log::debug!("channel opened: {:?}", event);
+    // The notification does not contain the channel id, so
+    // we look for it inside the peer channels.
+    let channels: ListPeerChannelsResponse = plugin
+        .state
+        .call("listpeerchannels", json::json!({ "id": event.id }))?;
+    let Some(channel) = channels
+        .channels
+        .into_iter()
+        .find(|channel| channel.funding_txid.as_ref() == Some(&event.funding_txid))
+    else {
+        return Ok(());
+    };
+    let Some(channel_id) = channel.channel_id else {
+        return Ok(());
+    };
+    // The peer funded the channel, so we know about the RGB
+    // amounts only from the consignment that it posted.
+    if channel.opener.as_deref() == Some("remote") {
+        // cln sorts the outputs of the funding transaction, so
+        // the funding output can be anywhere.
+        let funding_vout = channel.funding_outnum.ok_or(anyhow::anyhow!(
+            "cln does not report the funding output of the channel `{channel_id}`"
+        ))?;
+        plugin.state.manager().accept_rgb_funding(
+            &channel_id,
+            &event.funding_txid,
+            funding_vout,
+        )?;
+    }
+    if !event.channel_ready {
+        return Ok(());
+    }
+    confirm_rgb_channel(plugin, &channel_id, channel.short_channel_id)
+}
+
+pub fn on_channel_state_changed(plugin: &mut Plugin<State>, request: &Value) -> anyhow::Result<()> {
+    let event: ChannelStateChanged = parse_notification(request, "channel_state_changed")?;
+    log::debug!("channel state changed: {:?}", event);
+    match event.new_state.as_str() {
+        "CHANNELD_NORMAL" => confirm_rgb_channel(plugin, &event.channel_id, event.short_channel_id),
+        "CHANNELD_SHUTTING_DOWN"
+        | "CLOSINGD_SIGEXCHANGE"
+        | "CLOSINGD_COMPLETE"
+        | "AWAITING_UNILATERAL"
+        | "FUNDING_SPEND_SEEN"
+        | "ONCHAIN" => {
+            update_rgb_channel_state(plugin, &event.channel_id, RgbChannelState::Closing)
+        }
+        "CLOSED" => update_rgb_channel_state(plugin, &event.channel_id, RgbChannelState::Closed),
+        _ => Ok(()),
+    }
+}
+
+/// List the RGB channels, optionally only the ones with
+/// the peer `peer_id` or the ones that carry `asset_id`.
+pub fn rgb_list_channels(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    log::info!("rgblistchannels call with body `{request}`");
+    let request: RgbListChannelsRequest =
+        json::from_value(request).map_err(|err| error!("{err}"))?;
+    let manager = plugin.state.manager();
+    let asset_id = request
+        .asset_id
+        .map(|asset| manager.resolve_asset_id(&asset))
+        .transpose()
+        .map_err(|err| error!("{err}"))?;
+
+    let payload = match request.peer_id {
+        Some(ref peer_id) => json::json!({ "id": peer_id }),
+        None => json::json!({}),
+    };
+    let peer_channels: ListPeerChannelsResponse = plugin
+        .state
+        .call("listpeerchannels", payload)
+        .map_err(|err| error!("{err}"))?;
+    let mut peer_channels = peer_channels
+        .channels
+        .into_iter()
+        .filter_map(|channel| Some((channel.channel_id.clone()?, channel)))
+        .collect::<HashMap<_, _>>();
+
+    let tickers = manager
+        .list_assets()
+        .map_err(|err| error!("{err}"))?
+        .into_iter()
+        .map(|asset| (asset.asset_id, asset.ticker))
+        .collect::<HashMap<_, _>>();
+
+    let pending = manager
+        .list_rgb_channel_info(true)
+        .map_err(|err| error!("{err}"))?;
+    let confirmed = manager
+        .list_rgb_channel_info(false)
+        .map_err(|err| error!("{err}"))?;
+    let infos = pending
+        .into_iter()
+        .map(|info| (info, RgbChannelStatus::Pending))
+        .chain(
+            confirmed
+                .into_iter()
+                .map(|info| (info, RgbChannelStatus::Confirmed)),
+        );
+
+    let mut channels = vec![];
+    for (info, status) in infos {
+        let RgbInfo {
+            channel_id,
+            short_channel_id,
+            contract_id,
+            local_rgb_amount,
+            remote_rgb_amount,
+            state: rgb_state,
+            funding_ack,
+            ..
+        } = info;
+        let contract_id = contract_id.to_string();
+        if asset_id
+            .as_ref()
+            .is_some_and(|asset_id| asset_id != &contract_id)
+        {
+            continue;
+        }
+        let peer_channel = peer_channels.remove(&channel_id);
+        // cln only returns the channels of the peer that we asked for
+        if request.peer_id.is_some() && peer_channel.is_none() {
+            continue;
+        }
+        let (peer_id, state, funding_outpoint, cln_short_channel_id) = match peer_channel {
+            Some(channel) => {
+                let funding_outpoint = channel
+                    .funding_txid
+                    .zip(channel.funding_outnum)
+                    .map(|(txid, vout)| format!("{txid}:{vout}"));
+                (
+                    channel.peer_id,
+                    channel.state,
+                    funding_outpoint,
+                    channel.short_channel_id,
+                )
+            }
+            None => (None, None, None, None),
+        };
+        channels.push(RgbChannel {
+            peer_id,
+            short_channel_id: short_channel_id.or(cln_short_channel_id),
+            channel_id,
+            state,
+            status,
+            rgb_state,
+            ticker: tickers.get(&contract_id).cloned().flatten(),
+            contract_id,
+            local_rgb_amount,
+            remote_rgb_amount,
+            funding_outpoint,
+            funding_ack,
+        });
+    }
+    Ok(json::json!({ "channels": channels }))
+}
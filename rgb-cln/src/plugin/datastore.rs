@@ -0,0 +1,182 @@
+//! RGB Storage implementation on top of the Core Lightning datastore
+//!
+//! Keeping the RGB information inside the lightningd database means
+//! that they are replicated and backed up with the rest of the node state.
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use serde_json as json;
+
+use rgb_common::anyhow;
+use rgb_common::rgb_storage::RGBStorage;
+
+use crate::plugin::State;
+
+/// How many times a transaction is retried when the
+/// datastore entry was modified in the meanwhile.
+const MAX_TRANSACTION_RETRY: u8 = 5;
+
+#[derive(Debug, Deserialize)]
+struct ListDatastoreResponse {
+    datastore: Vec<DatastoreEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DatastoreEntry {
+    key: Vec<String>,
+    generation: Option<u64>,
+    string: Option<String>,
+}
+
+pub struct DatastoreStorage {
+    state: State,
+    /// Serialize the writes of this plugin, the datastore
+    /// generation protects us from the other writers.
+    lock: Mutex<()>,
+}
+
+impl DatastoreStorage {
+    /// Build the storage, the `state` must already know
+    /// the path of the cln rpc socket.
+    pub fn new(state: State) -> Self {
+        Self {
+            state,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The datastore wants the key as a list of strings, so
+    /// `rgb/channel/<id>` become `["rgb", "channel", "<id>"]`.
+    fn datastore_key(key: &str) -> Vec<String> {
+        key.split('/').map(|part| part.to_owned()).collect()
+    }
+
+    fn read(&self, key: &str) -> anyhow::Result<Option<DatastoreEntry>> {
+        let key = Self::datastore_key(key);
+        let response: ListDatastoreResponse = self
+            .state
+            .call("listdatastore", json::json!({ "key": key }))?;
+        let entry = response
+            .datastore
+            .into_iter()
+            .find(|entry| entry.key == key && entry.string.is_some());
+        Ok(entry)
+    }
+
+    fn write(
+        &self,
+        key: &str,
+        value: &str,
+        mode: &str,
+        generation: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let mut request = json::json!({
+            "key": Self::datastore_key(key),
+            "string": value,
+            "mode": mode,
+        });
+        if let Some(generation) = generation {
+            request["generation"] = json::json!(generation);
+        }
+        let _: json::Value = self.state.call("datastore", request)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str, generation: Option<u64>) -> anyhow::Result<()> {
+        let mut request = json::json!({
+            "key": Self::datastore_key(key),
+        });
+        if let Some(generation) = generation {
+            request["generation"] = json::json!(generation);
+        }
+        let _: json::Value = self.state.call("deldatastore", request)?;
+        Ok(())
+    }
+}
+
+impl RGBStorage for DatastoreStorage {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.read(key)?.and_then(|entry| entry.string))
+    }
+
+    fn put(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.write(key, value, "create-or-replace", None)
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        if self.read(key)?.is_none() {
+            return Ok(());
+        }
+        self.remove(key, None)
+    }
+
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let prefix = Self::datastore_key(prefix);
+        let response: ListDatastoreResponse = self
+            .state
+            .call("listdatastore", json::json!({ "key": prefix }))?;
+        let values = response
+            .datastore
+            .into_iter()
+            .filter(|entry| entry.key.len() == prefix.len() + 1 && entry.key.starts_with(&prefix))
+            .filter_map(|entry| Some((entry.key.join("/"), entry.string?)))
+            .collect();
+        Ok(values)
+    }
+
+    /// The datastore has no rename, so the value is written under `to`
+    /// and then removed from `from` only if nobody modified it in the meanwhile.
+    fn rename(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let entry = self
+            .read(from)?
+            .ok_or(anyhow::anyhow!("storage key `{from}` is not found"))?;
+        let value = entry
+            .string
+            .ok_or(anyhow::anyhow!("storage key `{from}` is not a string"))?;
+        self.write(to, &value, "create-or-replace", None)?;
+        self.remove(from, entry.generation)
+    }
+
+    fn transaction(
+        &self,
+        key: &str,
+        update: &mut dyn FnMut(Option<String>) -> anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()> {
+        for _ in 0..MAX_TRANSACTION_RETRY {
+            let (current, generation) = match self.read(key)? {
+                Some(entry) => (entry.string, entry.generation),
+                None => (None, None),
+            };
+            let exists = current.is_some();
+            let value = update(current)?;
+            let _guard = self.lock.lock().unwrap();
+            // The generation and the mode make the write fail if someone
+            // else modified the entry after our read.
+            let result = match value {
+                Some(value) if exists => self.write(key, &value, "must-replace", generation),
+                Some(value) => self.write(key, &value, "must-create", None),
+                None if exists => self.remove(key, generation),
+                None => return Ok(()),
+            };
+            let Err(err) = result else {
+                return Ok(());
+            };
+            // The error codes of the datastore are not exposed by the rpc
+            // client, so we look at the entry to know if we lost a race.
+            let modified = match self.read(key)? {
+                Some(entry) => !exists || entry.generation != generation,
+                None => exists,
+            };
+            if !modified {
+                return Err(err);
+            }
+            log::debug!("datastore entry `{key}` modified during the transaction, retrying");
+        }
+        anyhow::bail!(
+            "datastore transaction on `{key}` failed after {MAX_TRANSACTION_RETRY} retries"
+        )
+    }
+}
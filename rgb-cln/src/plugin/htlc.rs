@@ -0,0 +1,268 @@
+//! Tracking of the RGB amount carried by the HTLCs.
+//!
+//! The HTLCs are checked when they are accepted and the channel
+//! balances are moved only when the HTLCs are settled. A forwarding
+//! node reads the RGB amount from the onion payload, where the payer
+//! put it, and moves the balances of both the channels when the
+//! forward is settled.
+use serde::Deserialize;
+use serde_json as json;
+use serde_json::Value;
+
+use clightningrpc_plugin::errors::PluginError;
+use clightningrpc_plugin::plugin::Plugin;
+
+use rgb_common::anyhow;
+use rgb_common::onion;
+use rgb_common::types::{RgbForwardInfo, RgbHtlcDirection, RgbHtlcInfo};
+
+use crate::plugin::{parse_notification, State};
+
+#[derive(Debug, Deserialize)]
+struct HtlcAcceptedRequest {
+    onion: HtlcOnion,
+    htlc: Htlc,
+}
+
+#[derive(Debug, Deserialize)]
+struct HtlcOnion {
+    /// The hop payload, in hex.
+    payload: Option<String>,
+    /// Present only when we are asked to forward the HTLC.
+    short_channel_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Htlc {
+    short_channel_id: String,
+    payment_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct InvoicePayment {
+    label: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListInvoicesResponse {
+    invoices: Vec<Invoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Invoice {
+    payment_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendpaySuccess {
+    payment_hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendpayFailure {
+    data: SendpaySuccess,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForwardEvent {
+    /// Missing when cln is not able to decode the onion.
+    payment_hash: Option<String>,
+    status: String,
+}
+
+/// Read the RGB amount that the payer put inside the onion, if any.
+fn onion_rgb_amount(onion: &HtlcOnion) -> anyhow::Result<Option<u64>> {
+    let Some(ref payload) = onion.payload else {
+        return Ok(None);
+    };
+    onion::decode_rgb_amount(payload)
+}
+
+/// Check that both the channels of a forward carry the RGB amount
+/// written inside the onion, and return the reason if they do not.
+fn check_forward(
+    plugin: &Plugin<State>,
+    payment_hash: &str,
+    onion: &HtlcOnion,
+    in_channel_id: Option<String>,
+    out_short_channel_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let manager = plugin.state.manager();
+    let out_channel_id = manager.rgb_channel_id(out_short_channel_id)?;
+    let (in_channel_id, out_channel_id) = match (in_channel_id, out_channel_id) {
+        (None, None) => return Ok(None),
+        (Some(in_channel_id), Some(out_channel_id)) => (in_channel_id, out_channel_id),
+        _ => {
+            return Ok(Some(format!(
+                "htlc `{payment_hash}` can not be forwarded between an rgb and a non rgb channel"
+            )))
+        }
+    };
+    let amount = match onion_rgb_amount(onion) {
+        Ok(Some(amount)) => amount,
+        Ok(None) => {
+            return Ok(Some(format!(
+                "htlc `{payment_hash}` does not carry the rgb amount in the onion"
+            )))
+        }
+        Err(err) => {
+            return Ok(Some(format!(
+                "htlc `{payment_hash}` carries an invalid rgb amount: {err}"
+            )))
+        }
+    };
+    let in_info = manager.rgb_channel_info(&in_channel_id)?;
+    let out_info = manager.rgb_channel_info(&out_channel_id)?;
+    if in_info.contract_id != out_info.contract_id {
+        return Ok(Some(format!(
+            "channels `{in_channel_id}` and `{out_channel_id}` carry different contracts"
+        )));
+    }
+    if in_info.remote_rgb_amount < amount {
+        return Ok(Some(format!(
+            "channel `{in_channel_id}` has `{}` remote rgb amount, `{amount}` needed",
+            in_info.remote_rgb_amount
+        )));
+    }
+    if out_info.local_rgb_amount < amount {
+        return Ok(Some(format!(
+            "channel `{out_channel_id}` has `{}` local rgb amount, `{amount}` needed",
+            out_info.local_rgb_amount
+        )));
+    }
+
+    manager.add_rgb_forward(
+        payment_hash,
+        &RgbForwardInfo {
+            in_channel_id,
+            out_channel_id,
+            contract_id: in_info.contract_id,
+            amount,
+        },
+    )?;
+    Ok(None)
+}
+
+/// Check that the channels involved by the HTLC are able to carry
+/// the RGB amount, and return the reason if they are not.
+fn check_htlc(plugin: &mut Plugin<State>, request: Value) -> anyhow::Result<Option<String>> {
+    let request: HtlcAcceptedRequest = json::from_value(request)?;
+    let manager = plugin.state.manager();
+    let payment_hash = request.htlc.payment_hash;
+    let in_channel_id = manager.rgb_channel_id(&request.htlc.short_channel_id)?;
+
+    if let Some(ref out_short_channel_id) = request.onion.short_channel_id {
+        return check_forward(
+            plugin,
+            &payment_hash,
+            &request.onion,
+            in_channel_id,
+            out_short_channel_id,
+        );
+    }
+
+    let Some(payment) = manager.rgb_payment_info(&payment_hash)? else {
+        // not an RGB payment
+        return Ok(None);
+    };
+    let Some(in_channel_id) = in_channel_id else {
+        return Ok(Some(format!(
+            "htlc `{payment_hash}` is not received over an rgb channel"
+        )));
+    };
+    let in_info = manager.rgb_channel_info(&in_channel_id)?;
+    if in_info.contract_id != payment.contract_id {
+        return Ok(Some(format!(
+            "channel `{in_channel_id}` does not carry the contract `{}`",
+            payment.contract_id
+        )));
+    }
+    if in_info.remote_rgb_amount < payment.amount {
+        return Ok(Some(format!(
+            "channel `{in_channel_id}` has `{}` remote rgb amount, `{}` needed",
+            in_info.remote_rgb_amount, payment.amount
+        )));
+    }
+    // The payer may not write the amount in the onion, but when it
+    // does it must be what the invoice asks.
+    if let Ok(Some(amount)) = onion_rgb_amount(&request.onion) {
+        if amount < payment.amount {
+            return Ok(Some(format!(
+                "htlc `{payment_hash}` carries `{amount}` rgb amount, `{}` needed",
+                payment.amount
+            )));
+        }
+    }
+
+    manager.add_rgb_htlc(
+        &payment_hash,
+        &RgbHtlcInfo {
+            channel_id: in_channel_id,
+            contract_id: payment.contract_id,
+            amount: payment.amount,
+            direction: RgbHtlcDirection::Received,
+        },
+    )?;
+    Ok(None)
+}
+
+pub fn on_htlc_accepted(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    log::debug!("htlc accepted: {request}");
+    match check_htlc(plugin, request) {
+        Ok(None) => Ok(json::json!({ "result": "continue" })),
+        Ok(Some(reason)) => {
+            log::warn!("failing rgb htlc: {reason}");
+            // temporary_node_failure
+            Ok(json::json!({
+                "result": "fail",
+                "failure_message": "2002",
+            }))
+        }
+        Err(err) => {
+            // We never want to block the HTLC because of our errors
+            log::error!("failing to check the rgb htlc: {err}");
+            Ok(json::json!({ "result": "continue" }))
+        }
+    }
+}
+
+pub fn on_invoice_payment(plugin: &mut Plugin<State>, request: &Value) -> anyhow::Result<()> {
+    let event: InvoicePayment = parse_notification(request, "invoice_payment")?;
+    let invoices: ListInvoicesResponse = plugin
+        .state
+        .call("listinvoices", json::json!({ "label": event.label }))?;
+    for invoice in invoices.invoices {
+        plugin
+            .state
+            .manager()
+            .settle_rgb_htlc(&invoice.payment_hash)?;
+    }
+    Ok(())
+}
+
+pub fn on_sendpay_success(plugin: &mut Plugin<State>, request: &Value) -> anyhow::Result<()> {
+    let event: SendpaySuccess = parse_notification(request, "sendpay_success")?;
+    plugin.state.manager().settle_rgb_htlc(&event.payment_hash)
+}
+
+pub fn on_sendpay_failure(plugin: &mut Plugin<State>, request: &Value) -> anyhow::Result<()> {
+    let event: SendpayFailure = parse_notification(request, "sendpay_failure")?;
+    plugin
+        .state
+        .manager()
+        .fail_rgb_htlc(&event.data.payment_hash)
+}
+
+pub fn on_forward_event(plugin: &mut Plugin<State>, request: &Value) -> anyhow::Result<()> {
+    let event: ForwardEvent = parse_notification(request, "forward_event")?;
+    let Some(payment_hash) = event.payment_hash else {
+        return Ok(());
+    };
+    let manager = plugin.state.manager();
+    match event.status.as_str() {
+        "settled" => manager.settle_rgb_forward(&payment_hash),
+        "failed" | "local_failed" => manager.fail_rgb_forward(&payment_hash),
+        // the forward is still offered
+        _ => Ok(()),
+    }
+}
@@ -17,3 +17,21 @@ macro_rules! howmuchfees {
     }};
 }
 pub(super) use howmuchfees;
+
+/// Refuse the debug commands when the node is on mainnet, even
+/// if `rgb-allow-mainnet` is set.
+///
+/// The debug commands are:
+/// - `rgbinfo`
+/// - `rgbrefresh`, the refresh task already advances the transfers.
+macro_rules! refuse_on_mainnet {
+    ($cln:expr, $method:expr) => {{
+        if $cln.state.manager().is_mainnet() {
+            return Err(error!(
+                "`{}` is a debug command and it is disabled on mainnet",
+                $method
+            ));
+        }
+    }};
+}
+pub(super) use refuse_on_mainnet;
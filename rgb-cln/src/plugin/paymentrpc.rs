@@ -0,0 +1,391 @@
+//! RGB Lightning payments RPC methods
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+use serde_json::Value;
+
+use clightningrpc_plugin::error;
+use clightningrpc_plugin::errors::PluginError;
+use clightningrpc_plugin::plugin::Plugin;
+
+use rgb_common::anyhow;
+use rgb_common::core::ContractId;
+use rgb_common::onion::HopPayload;
+use rgb_common::types::{RgbHtlcDirection, RgbHtlcInfo, RgbInvoiceInfo, RgbPaymentInfo};
+
+use crate::plugin::State;
+
+#[derive(Deserialize)]
+pub struct RGBInvoiceRequest {
+    amount_msat: Value,
+    label: String,
+    description: String,
+    asset_id: String,
+    asset_amount: u64,
+    expiry: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InvoiceResponse {
+    bolt11: String,
+    payment_hash: String,
+    payment_secret: String,
+    expires_at: u64,
+}
+
+/// Create a Lightning invoice that should be paid with an RGB asset.
+pub fn rgb_invoice(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    log::info!("calling rgb invoice with body `{request}`");
+    let request: RGBInvoiceRequest = json::from_value(request).map_err(|err| error!("{err}"))?;
+    let asset_id = plugin
+        .state
+        .manager()
+        .resolve_asset_id(&request.asset_id)
+        .map_err(|err| error!("{err}"))?;
+    let contract_id = ContractId::from_str(&asset_id)
+        .map_err(|err| error!("decoding contract id return error: `{err}`"))?;
+    if request.asset_amount == 0 {
+        return Err(error!("`asset_amount` must be greater than zero"));
+    }
+
+    let rgb_info = RgbInvoiceInfo {
+        contract_id,
+        amount: request.asset_amount,
+    };
+    let mut payload = json::json!({
+        "amount_msat": request.amount_msat,
+        "label": request.label,
+        "description": rgb_info.encode_description(&request.description),
+    });
+    if let Some(expiry) = request.expiry {
+        payload["expiry"] = json::json!(expiry);
+    }
+    let invoice: InvoiceResponse = plugin
+        .state
+        .call("invoice", payload)
+        .map_err(|err| error!("{err}"))?;
+
+    let payment_info = RgbPaymentInfo {
+        contract_id,
+        amount: request.asset_amount,
+        local_rgb_amount: 0,
+        remote_rgb_amount: 0,
+        override_route_amount: false,
+    };
+    plugin
+        .state
+        .manager()
+        .add_rgb_payment_info(&invoice.payment_hash, &payment_info)
+        .map_err(|err| error!("{err}"))?;
+    Ok(json::json!({
+        "invoice": invoice,
+        "rgb_info": rgb_info,
+    }))
+}
+
+/// How many routes we ask to cln before giving up.
+const MAX_ROUTE_ATTEMPTS: u8 = 10;
+
+#[derive(Deserialize)]
+pub struct RGBPayRequest {
+    bolt11: String,
+    /// Required only when the invoice does not specify the amount.
+    amount_msat: Option<u64>,
+    riskfactor: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DecodeResponse {
+    payee: String,
+    amount_msat: Option<u64>,
+    payment_hash: String,
+    payment_secret: Option<String>,
+    description: Option<String>,
+    min_final_cltv_expiry: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetRouteResponse {
+    route: Vec<RouteHop>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RouteHop {
+    id: String,
+    channel: String,
+    direction: u8,
+    amount_msat: u64,
+    delay: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetInfoResponse {
+    blockheight: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateOnionResponse {
+    onion: String,
+    shared_secrets: Vec<String>,
+}
+
+/// Build the payload of every hop of the route, each one
+/// carries the RGB amount so the forwarding nodes know it.
+///
+/// The delays of the route are relative, so they are added to
+/// `base_expiry` as `sendpay` does.
+fn build_hop_payloads(
+    route: &[RouteHop],
+    invoice: &DecodeResponse,
+    rgb_info: &RgbInvoiceInfo,
+    amount_msat: u64,
+    base_expiry: u32,
+) -> anyhow::Result<Vec<Value>> {
+    let expiry = |hop: &RouteHop| -> anyhow::Result<u32> {
+        let expiry = u32::try_from(hop.delay)?
+            .checked_add(base_expiry)
+            .ok_or(anyhow::anyhow!(
+                "delay of the hop `{}` overflows",
+                hop.channel
+            ))?;
+        Ok(expiry)
+    };
+    let mut hops = vec![];
+    for (index, hop) in route.iter().enumerate() {
+        // Each node of the route reads where to forward the
+        // HTLC from its payload, that is the next hop.
+        let payload = match route.get(index + 1) {
+            Some(next) => HopPayload {
+                amt_to_forward: next.amount_msat,
+                outgoing_cltv_value: expiry(next)?,
+                short_channel_id: Some(next.channel.clone()),
+                payment_data: None,
+                rgb_amount: Some(rgb_info.amount),
+            },
+            None => HopPayload {
+                amt_to_forward: hop.amount_msat,
+                outgoing_cltv_value: expiry(hop)?,
+                short_channel_id: None,
+                payment_data: invoice
+                    .payment_secret
+                    .clone()
+                    .map(|secret| (secret, amount_msat)),
+                rgb_amount: Some(rgb_info.amount),
+            },
+        };
+        hops.push(json::json!({
+            "pubkey": hop.id,
+            "payload": payload.encode()?,
+        }));
+    }
+    Ok(hops)
+}
+
+/// Check the hop with the channel data that we have in the RGB storage.
+///
+/// Return `Ok(None)` when we know nothing about the channel, and an error
+/// when the channel is not able to carry the RGB amount.
+fn check_route_hop(
+    plugin: &Plugin<State>,
+    hop: &RouteHop,
+    rgb_info: &RgbInvoiceInfo,
+) -> anyhow::Result<Option<String>> {
+    let manager = plugin.state.manager();
+    let Some(channel_id) = manager.rgb_channel_id(&hop.channel)? else {
+        return Ok(None);
+    };
+    let info = manager.rgb_channel_info(&channel_id)?;
+    if info.contract_id != rgb_info.contract_id {
+        anyhow::bail!(
+            "channel `{channel_id}` does not carry the contract `{}`",
+            rgb_info.contract_id
+        );
+    }
+    if info.local_rgb_amount < rgb_info.amount {
+        anyhow::bail!(
+            "channel `{channel_id}` has `{}` local rgb amount, `{}` needed",
+            info.local_rgb_amount,
+            rgb_info.amount
+        );
+    }
+    Ok(Some(channel_id))
+}
+
+/// Look for a route where all the channels that we know are able
+/// to carry the RGB amount, and return it with the channel id of
+/// the first hop.
+fn find_rgb_route(
+    plugin: &Plugin<State>,
+    invoice: &DecodeResponse,
+    rgb_info: &RgbInvoiceInfo,
+    amount_msat: u64,
+    riskfactor: u64,
+) -> Result<(Vec<RouteHop>, String), PluginError> {
+    let mut exclude: Vec<String> = vec![];
+    for _ in 0..MAX_ROUTE_ATTEMPTS {
+        let mut payload = json::json!({
+            "id": invoice.payee,
+            "amount_msat": amount_msat,
+            "riskfactor": riskfactor,
+            "exclude": exclude,
+        });
+        if let Some(cltv) = invoice.min_final_cltv_expiry {
+            payload["cltv"] = json::json!(cltv);
+        }
+        let route: GetRouteResponse = plugin
+            .state
+            .call("getroute", payload)
+            .map_err(|err| error!("no route found: {err}"))?;
+
+        let mut first_hop = None;
+        let mut excluded = false;
+        for (index, hop) in route.route.iter().enumerate() {
+            match check_route_hop(plugin, hop, rgb_info) {
+                Ok(Some(channel_id)) if index == 0 => first_hop = Some(channel_id),
+                Ok(_) if index != 0 => continue,
+                Ok(_) => {
+                    log::debug!("first hop `{}` is not an rgb channel", hop.channel);
+                    exclude.push(format!("{}/{}", hop.channel, hop.direction));
+                    excluded = true;
+                    break;
+                }
+                Err(err) => {
+                    log::debug!("excluding hop `{}`: {err}", hop.channel);
+                    exclude.push(format!("{}/{}", hop.channel, hop.direction));
+                    excluded = true;
+                    break;
+                }
+            }
+        }
+        if excluded {
+            continue;
+        }
+        let first_hop = first_hop.ok_or(error!("route returned by cln is empty"))?;
+        return Ok((route.route, first_hop));
+    }
+    Err(error!(
+        "no route with enough `{}` liquidity found",
+        rgb_info.contract_id
+    ))
+}
+
+/// Send the payment along the `route` with an onion that carries the
+/// RGB amount, cln does not know how to put it inside the onion that
+/// `sendpay` builds.
+fn send_rgb_onion(
+    plugin: &Plugin<State>,
+    route: &[RouteHop],
+    invoice: &DecodeResponse,
+    rgb_info: &RgbInvoiceInfo,
+    amount_msat: u64,
+    bolt11: &str,
+) -> anyhow::Result<Value> {
+    let first = route
+        .first()
+        .ok_or(anyhow::anyhow!("route returned by cln is empty"))?;
+    let info: GetInfoResponse = plugin.state.call("getinfo", json::json!({}))?;
+    let hops = build_hop_payloads(route, invoice, rgb_info, amount_msat, info.blockheight + 1)?;
+    let onion: CreateOnionResponse = plugin.state.call(
+        "createonion",
+        json::json!({
+            "hops": hops,
+            "assocdata": invoice.payment_hash,
+        }),
+    )?;
+    plugin.state.call(
+        "sendonion",
+        json::json!({
+            "onion": onion.onion,
+            "first_hop": {
+                "id": first.id,
+                "amount_msat": first.amount_msat,
+                "delay": first.delay,
+            },
+            "payment_hash": invoice.payment_hash,
+            "shared_secrets": onion.shared_secrets,
+            "bolt11": bolt11,
+            "destination": invoice.payee,
+            "amount_msat": amount_msat,
+        }),
+    )
+}
+
+/// Pay an RGB Lightning invoice over RGB channels.
+pub fn rgb_pay(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    log::info!("calling rgb pay with body `{request}`");
+    let request: RGBPayRequest = json::from_value(request).map_err(|err| error!("{err}"))?;
+    let invoice: DecodeResponse = plugin
+        .state
+        .call("decode", json::json!({ "string": request.bolt11 }))
+        .map_err(|err| error!("{err}"))?;
+    let description = invoice
+        .description
+        .clone()
+        .ok_or(error!("invoice without description is not an rgb invoice"))?;
+    let rgb_info = RgbInvoiceInfo::decode_description(&description)
+        .map_err(|err| error!("{err}"))?
+        .ok_or(error!("invoice `{}` is not an rgb invoice", request.bolt11))?;
+    let amount_msat = invoice.amount_msat.or(request.amount_msat).ok_or(error!(
+        "`amount_msat` is required by an invoice without amount"
+    ))?;
+
+    let (route, first_hop) = find_rgb_route(
+        plugin,
+        &invoice,
+        &rgb_info,
+        amount_msat,
+        request.riskfactor.unwrap_or(10),
+    )?;
+    log::debug!("rgb route found: {:?}", route);
+
+    let manager = plugin.state.manager();
+    let channel = manager
+        .rgb_channel_info(&first_hop)
+        .map_err(|err| error!("{err}"))?;
+    let payment_info = RgbPaymentInfo {
+        contract_id: rgb_info.contract_id,
+        amount: rgb_info.amount,
+        local_rgb_amount: channel.local_rgb_amount,
+        remote_rgb_amount: channel.remote_rgb_amount,
+        override_route_amount: true,
+    };
+    manager
+        .add_rgb_payment_info(&invoice.payment_hash, &payment_info)
+        .map_err(|err| error!("{err}"))?;
+    manager
+        .add_rgb_htlc(
+            &invoice.payment_hash,
+            &RgbHtlcInfo {
+                channel_id: first_hop,
+                contract_id: rgb_info.contract_id,
+                amount: rgb_info.amount,
+                direction: RgbHtlcDirection::Offered,
+            },
+        )
+        .map_err(|err| error!("{err}"))?;
+
+    // The HTLC is settled or failed by the sendpay notifications, we can
+    // not wait for the result here without blocking the whole plugin.
+    let sendpay = send_rgb_onion(
+        plugin,
+        &route,
+        &invoice,
+        &rgb_info,
+        amount_msat,
+        &request.bolt11,
+    );
+    let sendpay = match sendpay {
+        Ok(sendpay) => sendpay,
+        Err(err) => {
+            let _ = manager.fail_rgb_htlc(&invoice.payment_hash);
+            return Err(error!("{err}"));
+        }
+    };
+    Ok(json::json!({
+        "sendpay": sendpay,
+        "rgb_info": rgb_info,
+    }))
+}
@@ -0,0 +1,136 @@
+//! PSBT signer on top of Core Lightning
+//!
+//! Core Lightning sign the inputs that belong to its wallet with
+//! `signpsbt`, while the inputs of the RGB wallet are signed with
+//! the plugin key that cln derived for us with `makesecret`.
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde_json as json;
+
+use rgb_common::anyhow;
+use rgb_common::bitcoin::bip32::ExtendedPrivKey;
+use rgb_common::bitcoin::psbt::PartiallySignedTransaction;
+use rgb_common::bitcoin::Network;
+
+use crate::plugin::State;
+
+#[derive(Debug, Deserialize)]
+struct MakeSecretResponse {
+    secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListFundsResponse {
+    outputs: Vec<FundOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FundOutput {
+    txid: String,
+    output: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignPsbtResponse {
+    signed_psbt: String,
+}
+
+/// Derive the plugin master key from a secret generated by cln,
+/// so we do not need to read the `hsm_secret` (that may be encrypted).
+pub fn derive_master_key(state: &State, network: &str) -> anyhow::Result<ExtendedPrivKey> {
+    let secret: MakeSecretResponse =
+        state.call("makesecret", json::json!({ "string": "rgb-cln/wallet" }))?;
+    let seed = hex::decode(secret.secret)?;
+    let network = Network::from_str(network)?;
+    let xprv = ExtendedPrivKey::new_master(network, &seed)?;
+    Ok(xprv)
+}
+
+/// Return the index of the psbt inputs that belong to the cln wallet.
+fn cln_inputs(state: &State, psbt: &PartiallySignedTransaction) -> anyhow::Result<Vec<usize>> {
+    let funds: ListFundsResponse = state.call("listfunds", json::json!({}))?;
+    let outpoints = funds
+        .outputs
+        .iter()
+        .map(|output| format!("{}:{}", output.txid, output.output))
+        .collect::<HashSet<_>>();
+    let inputs = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| outpoints.contains(&input.previous_output.to_string()))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    Ok(inputs)
+}
+
+/// The inputs of the cln wallet reserved by [`sign_psbt`].
+///
+/// cln keeps them reserved until the transaction is broadcast, so
+/// they must be released if something fails after the signature.
+#[derive(Debug, Default)]
+pub struct Reservation {
+    psbt: Option<String>,
+}
+
+impl Reservation {
+    /// Give back the reserved inputs to the cln wallet.
+    pub fn release(self, state: &State) {
+        let Some(psbt) = self.psbt else {
+            return;
+        };
+        let unreserve: anyhow::Result<json::Value> =
+            state.call("unreserveinputs", json::json!({ "psbt": psbt }));
+        if let Err(err) = unreserve {
+            log::warn!("failing to unreserve the inputs of `{psbt}`: {err}");
+        }
+    }
+}
+
+/// Sign the psbt with cln and with the RGB wallet.
+///
+/// The inputs of the cln wallet are reserved inside `reservation`,
+/// the caller must release them if the transaction is not broadcast.
+pub fn sign_psbt(
+    state: &State,
+    psbt: &mut PartiallySignedTransaction,
+    reservation: &mut Reservation,
+) -> anyhow::Result<()> {
+    let cln_inputs = cln_inputs(state, psbt)?;
+    let wallet = state.manager().wallet();
+    let finalized = wallet.sign_psbt(psbt)?;
+    if cln_inputs.is_empty() {
+        if !finalized {
+            anyhow::bail!("not able to sign the psbt `{psbt}`");
+        }
+        return Ok(());
+    }
+
+    // cln refuse to reserve inputs that it does not know, so
+    // we build a psbt with only its inputs.
+    let mut reserve_tx = psbt.unsigned_tx.clone();
+    reserve_tx.input = cln_inputs
+        .iter()
+        .map(|index| psbt.unsigned_tx.input[*index].clone())
+        .collect();
+    reserve_tx.output = vec![];
+    let reserve_psbt = PartiallySignedTransaction::from_unsigned_tx(reserve_tx)?;
+    let _: json::Value = state.call(
+        "reserveinputs",
+        json::json!({ "psbt": reserve_psbt.to_string() }),
+    )?;
+    reservation.psbt = Some(reserve_psbt.to_string());
+
+    let signed: SignPsbtResponse = state.call(
+        "signpsbt",
+        json::json!({
+            "psbt": psbt.to_string(),
+            "signonly": cln_inputs,
+        }),
+    )?;
+    *psbt = PartiallySignedTransaction::from_str(&signed.signed_psbt)?;
+    wallet.finalize_psbt(psbt)
+}
@@ -12,12 +12,16 @@ use clightningrpc_plugin::error;
 use clightningrpc_plugin::errors::PluginError;
 use clightningrpc_plugin::plugin::Plugin;
 
+use rgb_common::anyhow;
 use rgb_common::core::ContractId;
+use rgb_common::lib::wallet::{TransferKind, TransferStatus};
 use rgb_common::{bitcoin30, types};
+use rgb_common::{RGBManager, Wallet};
 
-use rgb_common::types::RgbInfo;
+use rgb_common::types::{RgbChannelState, RgbInfo};
 
 use crate::plugin::macros::howmuchfees;
+use crate::plugin::signer;
 use crate::plugin::State;
 
 #[derive(Deserialize, Serialize)]
@@ -29,23 +33,38 @@ pub struct RGBBalanceRequest {
 pub fn rgb_balance(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     log::info!("rgbbalances call with body `{request}`");
     let request: RGBBalanceRequest = json::from_value(request).map_err(|err| error!("{err}"))?;
-    let mut assets_balance = json_utils::init_payload();
-    if let Some(asset_id) = request.asset_id {
-        let balance = plugin.state.manager().assert_balance(asset_id);
-        assets_balance = match balance {
+    let manager = plugin.state.manager();
+    let assets_balance = match request.asset_id {
+        Some(asset) => match manager
+            .resolve_asset_id(&asset)
+            .and_then(|asset_id| manager.assert_balance(asset_id))
+        {
             Ok(balance) => json::to_value(balance).map_err(|err| error!("{err}"))?,
             Err(err) => json::json!({
                 "warning": err.to_string(),
             }),
-        };
-    }
+        },
+        // without an asset we report every asset, whatever is its schema
+        None => {
+            let mut assets = json_utils::init_payload();
+            let list = plugin
+                .state
+                .manager()
+                .list_assets()
+                .map_err(|err| error!("{err}"))?;
+            for asset in list {
+                assets[asset.asset_id.as_str()] = json::json!({
+                    "schema": asset.schema,
+                    "name": asset.name,
+                    "balance": asset.balance,
+                });
+            }
+            assets
+        }
+    };
 
-    let btc_balance = plugin
-        .state
-        .manager()
-        .wallet()
-        .get_btc_balance()
-        .map_err(|err| error!("{err}"))?;
+    // `null` until the refresh task syncs the wallets for the first time
+    let btc_balance = plugin.state.manager().onchain_balance();
     let balance = json::json!({
         "onchain": btc_balance,
         "assets": assets_balance,
@@ -53,46 +72,233 @@ pub fn rgb_balance(plugin: &mut Plugin<State>, request: Value) -> Result<Value,
     Ok(json::to_value(balance)?)
 }
 
+/// Return a new bitcoin address of the RGB wallet, the funds sent
+/// there pay the asset issuances and the RGB channel fundings.
+pub fn rgb_new_addr(plugin: &mut Plugin<State>, _: Value) -> Result<Value, PluginError> {
+    let address = plugin
+        .state
+        .manager()
+        .wallet()
+        .new_addr()
+        .map_err(|err| error!("{err}"))?;
+    Ok(json::json!({ "address": address }))
+}
+
+/// List every asset known by the RGB wallet.
+pub fn rgb_list_assets(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
+    log::info!("rgblistassets call with body `{request}`");
+    let assets = plugin
+        .state
+        .manager()
+        .list_assets()
+        .map_err(|err| error!("{err}"))?;
+    Ok(json::json!({ "assets": assets }))
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum RgbTransferDirection {
+    Incoming,
+    Outgoing,
+    Issuance,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum RgbTransferStatus {
+    WaitingCounterparty,
+    WaitingConfirmations,
+    Settled,
+    Failed,
+}
+
+#[derive(Deserialize)]
+struct RgbListTransfersRequest {
+    asset_id: Option<String>,
+    direction: Option<RgbTransferDirection>,
+    status: Option<RgbTransferStatus>,
+}
+
+fn transfer_direction(kind: &TransferKind) -> RgbTransferDirection {
+    match kind {
+        TransferKind::Issuance => RgbTransferDirection::Issuance,
+        TransferKind::ReceiveBlind | TransferKind::ReceiveWitness => RgbTransferDirection::Incoming,
+        TransferKind::Send => RgbTransferDirection::Outgoing,
+    }
+}
+
+fn transfer_status(status: &TransferStatus) -> RgbTransferStatus {
+    match status {
+        TransferStatus::WaitingCounterparty => RgbTransferStatus::WaitingCounterparty,
+        TransferStatus::WaitingConfirmations => RgbTransferStatus::WaitingConfirmations,
+        TransferStatus::Settled => RgbTransferStatus::Settled,
+        TransferStatus::Failed => RgbTransferStatus::Failed,
+    }
+}
+
+/// List the on-chain RGB transfers, filtered by asset, direction and status.
+pub fn rgb_list_transfers(
+    plugin: &mut Plugin<State>,
+    request: Value,
+) -> Result<Value, PluginError> {
+    log::info!("rgblisttransfers call with body `{request}`");
+    let request: RgbListTransfersRequest =
+        json::from_value(request).map_err(|err| error!("{err}"))?;
+    let manager = plugin.state.manager();
+    let asset_id = request
+        .asset_id
+        .map(|asset| manager.resolve_asset_id(&asset))
+        .transpose()
+        .map_err(|err| error!("{err}"))?;
+    let transfers = manager
+        .wallet()
+        .list_transfers(asset_id)
+        .map_err(|err| error!("{err}"))?
+        .into_iter()
+        .filter(|transfer| {
+            request.direction.as_ref().map_or(true, |direction| {
+                &transfer_direction(&transfer.kind) == direction
+            })
+        })
+        .filter(|transfer| {
+            request
+                .status
+                .as_ref()
+                .map_or(true, |status| &transfer_status(&transfer.status) == status)
+        })
+        .collect::<Vec<_>>();
+    Ok(json::json!({ "transfers": transfers }))
+}
+
+/// Largest channel that a peer without `option_support_large_channel` accepts.
+const MAX_FUNDING_SAT: u64 = (1 << 24) - 1;
+/// Virtual size reserved for the fees of the funding
+/// transaction when the capacity is `all`.
+const FUNDING_FEE_RESERVE_VB: f32 = 1000.0;
+
 #[derive(Deserialize, Serialize)]
 pub struct RGBFundChannelRequest {
     peer_id: String,
-    amount_msat: u64,
+    /// Channel capacity in sat, or `all` to use every
+    /// spendable sat of the RGB wallet.
+    amount_sat: Value,
+    /// The contract id, or an alias, ticker or name of the asset
     asset_id: String,
+    /// Asset amount that we put inside the channel
+    asset_amount: u64,
+    /// Part of `asset_amount` that is given to the peer
+    #[serde(default)]
+    push_asset_amount: u64,
+}
+
+impl RGBFundChannelRequest {
+    fn capacity_sat(&self, wallet: &Wallet, fee_rate: f32) -> Result<u64, PluginError> {
+        let capacity = match &self.amount_sat {
+            Value::Number(amount) => amount
+                .as_u64()
+                .ok_or(error!("`amount_sat` must be a positive integer"))?,
+            Value::String(amount) if amount == "all" => {
+                let spendable = wallet.spendable_btc_sat().map_err(|err| error!("{err}"))?;
+                let fee_reserve = (FUNDING_FEE_RESERVE_VB * fee_rate).ceil() as u64;
+                spendable.saturating_sub(fee_reserve).min(MAX_FUNDING_SAT)
+            }
+            Value::String(amount) => amount
+                .parse()
+                .map_err(|_| error!("`amount_sat` must be a number of sat or `all`"))?,
+            _ => return Err(error!("`amount_sat` must be a number of sat or `all`")),
+        };
+        if capacity == 0 {
+            return Err(error!("not enough funds to open a channel"));
+        }
+        Ok(capacity)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct FundincStartResponse {
+    /// Only the recent version of cln return the
+    /// temporary channel id.
+    channel_id: Option<String>,
     funding_address: String,
     scriptpubkey: String,
     close_to: String,
     channel_type: json::Value,
 }
 
+impl FundincStartResponse {
+    /// The id used to store the channel info until we do not
+    /// know the final channel id.
+    ///
+    /// When cln does not give us the temporary channel id we fall back
+    /// to the funding scriptpubkey, that is unique for each channel.
+    fn temporary_channel_id(&self) -> String {
+        self.channel_id.clone().unwrap_or(self.scriptpubkey.clone())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FundingCompleteResponse {
+    channel_id: String,
+    commitments_secured: bool,
+}
+
+/// Undo a funding that failed after `fundchannel_start`: forget the
+/// channel info, give back the reserved inputs and cancel the open.
+fn cancel_rgb_funding(
+    plugin: &Plugin<State>,
+    peer_id: &str,
+    temporary_channel_id: &str,
+    reservation: signer::Reservation,
+) {
+    if let Err(err) = plugin
+        .state
+        .manager()
+        .remove_rgb_info(temporary_channel_id, true)
+    {
+        log::warn!("failing to remove the rgb info of `{temporary_channel_id}`: {err}");
+    }
+    reservation.release(&plugin.state);
+    let cancel: anyhow::Result<json::Value> = plugin
+        .state
+        .call("fundchannel_cancel", json::json!({ "id": peer_id }));
+    if let Err(err) = cancel {
+        log::warn!("failing to cancel the funding with `{peer_id}`: {err}");
+    }
+}
+
 /// Opening a RGB channel
 pub fn fund_rgb_channel(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     log::info!("calling fund rgb channel with `{request}`");
     let request: RGBFundChannelRequest = json::from_value(request)?;
-    // FIXME: We should give a string like Tether/USTD and calculate the asset id in here
-    // check if the asset id is valit
-    let contract_id = ContractId::from_str(&request.asset_id)
+    let manager = plugin.state.manager();
+    let asset_id = manager
+        .resolve_asset_id(&request.asset_id)
+        .map_err(|err| error!("{err}"))?;
+    let contract_id = ContractId::from_str(&asset_id)
         .map_err(|err| error!("decoding contract id return error: `{err}`"))?;
     // FIXME: Check if we are connected with the peer otherwise connect to them
 
-    // FIXME: we need the magic of core lightning here
-    let balance = request.amount_msat;
-    let assert_balance = plugin
-        .state
-        .manager()
+    if request.asset_amount == 0 {
+        return Err(error!("`asset_amount` must be greater than zero"));
+    }
+    if request.push_asset_amount > request.asset_amount {
+        return Err(error!(
+            "`push_asset_amount` `{}` is greater than `asset_amount` `{}`",
+            request.push_asset_amount, request.asset_amount
+        ));
+    }
+    let asset_balance = manager
         .assert_balance(contract_id.to_string())
         .map_err(|err| error!("{err}"))?;
-    log::info!("rgbalance {:?}", balance);
-
-    if balance < assert_balance.spendable {
+    log::info!("rgb balance {:?}", asset_balance);
+    if request.asset_amount > asset_balance.spendable {
         return Err(error!(
-            "Balance avaialbe `{}` is not enough to open a channel of `{}` capacity",
-            assert_balance.spendable, balance
+            "Balance available `{}` is not enough to put `{}` inside the channel",
+            asset_balance.spendable, request.asset_amount
         ));
     }
+    let capacity_sat =
+        request.capacity_sat(&manager.wallet(), manager.config().funding_fee_rate)?;
 
     let fundchannel: FundincStartResponse = plugin
         .state
@@ -100,68 +306,98 @@ pub fn fund_rgb_channel(plugin: &mut Plugin<State>, request: Value) -> Result<Va
             "fundchannel_start",
             json::json!({
                 "id": request.peer_id,
-                "amount": balance.to_string(),
+                "amount": capacity_sat,
             }),
         )
         .map_err(|err| error!("{err}"))?;
+    let temporary_channel_id = fundchannel.temporary_channel_id();
     let Ok(scriptpubkey) = bitcoin30::ScriptBuf::from_hex(&fundchannel.scriptpubkey) else {
-        let _: json::Value = plugin
-            .state
-            .call(
-                "fundchannel_cancel",
-                json::json!({
-                    "id": request.peer_id,
-                }),
-            )
-            .map_err(|err| error!("{err}"))?;
+        let reservation = signer::Reservation::default();
+        cancel_rgb_funding(plugin, &request.peer_id, &temporary_channel_id, reservation);
         return Err(error!("Impossible parse `scriptpubkey`, failing funding"));
     };
 
     let info = RgbInfo {
-        channel_id: "<channel_id>".to_owned(),
+        channel_id: temporary_channel_id.clone(),
+        short_channel_id: None,
         contract_id,
-        local_rgb_amount: balance,
+        local_rgb_amount: request.asset_amount - request.push_asset_amount,
         // FIXME: Check that we are not opening a dual funding channel with
         // liquidity ads
-        remote_rgb_amount: 0,
+        remote_rgb_amount: request.push_asset_amount,
+        state: RgbChannelState::Opening,
+        funding_txid: None,
+        funding_ack: None,
     };
 
-    plugin
-        .state
-        .manager()
-        .add_rgb_info(&info, true)
-        .map_err(|err| error!("{err}"))?;
-    let Ok(psbt) =
-        plugin
-            .state
-            .manager()
-            .build_rgb_funding_transaction(&info, scriptpubkey, 1.1, 6)
-    else {
-        let _: json::Value = plugin
-            .state
-            .call(
-                "fundchannel_cancel",
-                json::json!({
-                    "id": request.peer_id,
-                }),
-            )
-            .map_err(|err| error!("{err}"))?;
+    if let Err(err) = manager.add_rgb_info(&info, true) {
+        let reservation = signer::Reservation::default();
+        cancel_rgb_funding(plugin, &request.peer_id, &temporary_channel_id, reservation);
+        return Err(error!("{err}"));
+    }
+    let mut reservation = signer::Reservation::default();
+    let psbt = manager.build_rgb_funding_transaction(
+        &info,
+        scriptpubkey,
+        capacity_sat,
+        manager.config().funding_fee_rate,
+        manager.wallet().min_confirmations(),
+        |psbt| signer::sign_psbt(&plugin.state, psbt, &mut reservation),
+    );
+    let psbt = match psbt {
+        Ok(psbt) => psbt,
+        Err(err) => {
+            cancel_rgb_funding(plugin, &request.peer_id, &temporary_channel_id, reservation);
+            return Err(error!(
+                "failing to build the rgb funding transaction: {err}"
+            ));
+        }
+    };
+
+    let fundchannel: anyhow::Result<FundingCompleteResponse> = plugin.state.call(
+        "fundchannel_complete",
+        json::json!({
+            "id": request.peer_id,
+            "psbt": psbt.to_string(),
+        }),
+    );
+    let fundchannel = match fundchannel {
+        Ok(fundchannel) if fundchannel.commitments_secured => fundchannel,
+        Ok(fundchannel) => {
+            cancel_rgb_funding(plugin, &request.peer_id, &temporary_channel_id, reservation);
+            return Err(error!(
+                "Commitments of the channel `{}` are not secured",
+                fundchannel.channel_id
+            ));
+        }
+        Err(err) => {
+            cancel_rgb_funding(plugin, &request.peer_id, &temporary_channel_id, reservation);
+            return Err(error!("`fundchannel_complete` failed: {err}"));
+        }
+    };
+    // cln expects us to broadcast the funding transaction once the
+    // commitments are secured, after that the inputs are spent.
+    if let Err(err) = manager.broadcast_rgb_funding_transaction(&psbt) {
+        cancel_rgb_funding(plugin, &request.peer_id, &temporary_channel_id, reservation);
         return Err(error!(
-            "Impossible .build_rgb_funding_transaction, failing funding"
+            "failing to broadcast the funding transaction: {err}"
         ));
+    }
+    // Now that cln told us the final channel id we can store the info
+    // under it, the channel is confirmed when it is locked in. The funding
+    // transaction is already broadcast, so a failure here does not fail the
+    // funding: `recover_rgb_info` finishes an interrupted rename at restart.
+    let info = match manager.rename_pending_rgb_info(&temporary_channel_id, &fundchannel.channel_id)
+    {
+        Ok(info) => info,
+        Err(err) => {
+            log::error!(
+                "failing to move the rgb info of `{temporary_channel_id}` under `{}`: {err}",
+                fundchannel.channel_id
+            );
+            info
+        }
     };
-
-    let psbt = psbt.serialize_hex();
-    let fundchannel: json::Value = plugin
-        .state
-        .call(
-            "fundchannel_complete",
-            json::json!({
-                "id": request.peer_id,
-                "psbt": psbt,
-            }),
-        )
-        .map_err(|err| error!("{err}"))?;
     Ok(json::json!({
         "info": fundchannel,
         "rgb_info": info,
@@ -194,56 +430,240 @@ pub fn rgb_issue_new_assert(
     Ok(json::to_value(assert)?)
 }
 
+#[derive(Deserialize, Debug)]
+pub struct NewAssetCFARequest {
+    amounts: Vec<u64>,
+    name: String,
+    description: Option<String>,
+    precision: u8,
+    /// Path of the media file attached to the contract.
+    file_path: Option<String>,
+}
+
+pub fn rgb_issue_new_asset_cfa(
+    plugin: &mut Plugin<State>,
+    request: Value,
+) -> Result<Value, PluginError> {
+    log::info!("calling rgb issue asset cfa with request body: `{request}`");
+    let request: NewAssetCFARequest = json::from_value(request)?;
+    let asset = plugin
+        .state
+        .manager()
+        .issue_asset_cfa(
+            request.name,
+            request.description,
+            request.precision,
+            request.amounts,
+            request.file_path,
+        )
+        .map_err(|err| error!("{err}"))?;
+    Ok(json::to_value(asset)?)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewAssetUDARequest {
+    ticker: String,
+    name: String,
+    details: Option<String>,
+    precision: u8,
+    /// Path of the main media file of the collectible.
+    media_file_path: Option<String>,
+    /// Path of the other files attached to the collectible.
+    #[serde(default)]
+    attachments_file_paths: Vec<String>,
+}
+
+pub fn rgb_issue_new_asset_uda(
+    plugin: &mut Plugin<State>,
+    request: Value,
+) -> Result<Value, PluginError> {
+    log::info!("calling rgb issue asset uda with request body: `{request}`");
+    let request: NewAssetUDARequest = json::from_value(request)?;
+    let asset = plugin
+        .state
+        .manager()
+        .issue_asset_uda(
+            request.ticker,
+            request.name,
+            request.details,
+            request.precision,
+            request.media_file_path,
+            request.attachments_file_paths,
+        )
+        .map_err(|err| error!("{err}"))?;
+    Ok(json::to_value(asset)?)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum RgbReceiveMode {
+    /// Receive on a colored UTXO that we own, hidden by a blinding factor.
+    #[default]
+    Blinded,
+    /// Receive on a new output of the sender transaction.
+    Witness,
+}
+
 #[derive(Deserialize)]
 struct RgbReceiveRequest {
     asset_id: Option<String>,
+    /// Amount that the invoice asks to the sender.
+    amount: Option<u64>,
+    /// How many seconds the invoice stays valid.
+    expiry: Option<u32>,
+    #[serde(default)]
+    mode: RgbReceiveMode,
 }
 
 pub fn rgb_receive(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     log::info!("calling rgb receive with body `{request}`");
     let request: RgbReceiveRequest = json::from_value(request).map_err(|err| error!("{err}"))?;
-    let wallet = plugin.state.manager().wallet();
+    let manager = plugin.state.manager();
+    let asset_id = request
+        .asset_id
+        .map(|asset| manager.resolve_asset_id(&asset))
+        .transpose()
+        .map_err(|err| error!("{err}"))?;
+    let wallet = manager.wallet();
+    if let RgbReceiveMode::Witness = request.mode {
+        log::info!("get the new witness receive");
+        let receive = wallet
+            .new_witness_receive(
+                asset_id,
+                request.amount,
+                request.expiry,
+                wallet.min_confirmations(),
+            )
+            .map_err(|err| error!("{err}"))?;
+        return Ok(json::json!(receive));
+    }
+
     let fee = howmuchfees!(plugin);
     log::info!("creating utxo with fee `{fee}`");
-    wallet
-        .create_utxos(fee as f32, |psbt| wallet.sing_with_master_key(psbt))
-        .map_err(|err| error!("{err}"))?;
+    let mut reservation = signer::Reservation::default();
+    let utxos = wallet.create_utxos(fee as f32, |psbt| {
+        signer::sign_psbt(&plugin.state, psbt, &mut reservation)
+    });
+    if let Err(err) = utxos {
+        reservation.release(&plugin.state);
+        return Err(error!("{err}"));
+    }
     log::info!("get the new blind receive");
     let receive = wallet
-        // FIXME: add the blocks inside the plugin configuration
-        .new_blind_receive(request.asset_id, 6)
+        .new_blind_receive(
+            asset_id,
+            request.amount,
+            request.expiry,
+            wallet.min_confirmations(),
+        )
         .map_err(|err| error!("{err}"))?;
     Ok(json::json!(receive))
 }
 
+/// A recipient is described by an RGB `invoice`, or by
+/// `asset_id`, `amount` and `blinded_utxo`.
+///
+/// A witness receive is paid by setting `witness_amount_sat`.
+#[derive(Deserialize, Default)]
+struct RgbSendRecipientRequest {
+    invoice: Option<String>,
+    asset_id: Option<String>,
+    amount: Option<u64>,
+    #[serde(alias = "recipient_id")]
+    blinded_utxo: Option<String>,
+    witness_amount_sat: Option<u64>,
+}
+
+impl RgbSendRecipientRequest {
+    fn is_empty(&self) -> bool {
+        self.invoice.is_none()
+            && self.asset_id.is_none()
+            && self.amount.is_none()
+            && self.blinded_utxo.is_none()
+    }
+
+    fn into_recipient(self, manager: &RGBManager) -> Result<types::RGBSendRecipient, PluginError> {
+        if let Some(invoice) = self.invoice {
+            if self.asset_id.is_some() || self.blinded_utxo.is_some() {
+                return Err(error!(
+                    "`asset_id` and `blinded_utxo` are already specified by the invoice"
+                ));
+            }
+            return manager
+                .wallet()
+                .recipient_from_invoice(&invoice, self.amount, self.witness_amount_sat)
+                .map_err(|err| error!("{err}"));
+        }
+        match (self.asset_id, self.amount, self.blinded_utxo) {
+            (Some(asset), Some(amount), Some(blinded_utxo)) => Ok(types::RGBSendRecipient {
+                asset_id: manager
+                    .resolve_asset_id(&asset)
+                    .map_err(|err| error!("{err}"))?,
+                amount,
+                blinded_utxo,
+                witness_amount_sat: self.witness_amount_sat,
+                transport_endpoints: vec![],
+            }),
+            _ => Err(error!(
+                "specify an `invoice` or `asset_id`, `amount` and `blinded_utxo`"
+            )),
+        }
+    }
+}
+
+/// Pay a single recipient, or many recipients of different assets with `recipients`.
 #[derive(Deserialize)]
 struct RgbSendRequest {
-    asset_id: String,
-    amount: u64,
-    blinded_utxo: String,
+    #[serde(flatten)]
+    recipient: RgbSendRecipientRequest,
+    recipients: Option<Vec<RgbSendRecipientRequest>>,
+    #[serde(default)]
     donation: bool,
 }
 
-impl Into<types::RGBSendAssetData> for RgbSendRequest {
-    fn into(self) -> types::RGBSendAssetData {
-        types::RGBSendAssetData {
-            asset_id: self.asset_id,
-            amount: self.amount,
-            blinded_utxo: self.blinded_utxo,
-            donation: self.donation,
+impl RgbSendRequest {
+    fn into_send_data(self, manager: &RGBManager) -> Result<types::RGBSendAssetData, PluginError> {
+        let recipients = match self.recipients {
+            Some(_) if !self.recipient.is_empty() => {
+                return Err(error!(
+                    "specify a single recipient or `recipients`, not both"
+                ))
+            }
+            Some(recipients) => recipients,
+            None => vec![self.recipient],
+        };
+        if recipients.is_empty() {
+            return Err(error!("`recipients` can not be empty"));
         }
+        let recipients = recipients
+            .into_iter()
+            .map(|recipient| recipient.into_recipient(manager))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(types::RGBSendAssetData {
+            recipients,
+            donation: self.donation,
+        })
     }
 }
 
 pub fn rgb_send(plugin: &mut Plugin<State>, request: Value) -> Result<Value, PluginError> {
     log::info!("calling rgb send with body `{request}`");
     let request: RgbSendRequest = json::from_value(request).map_err(|err| error!("{err}"))?;
-    let wallet = plugin.state.manager().wallet();
+    let manager = plugin.state.manager();
+    let data = request.into_send_data(&manager)?;
+    let wallet = manager.wallet();
     let fee = howmuchfees!(plugin);
-    let minconf = 6;
-    let send = wallet.send_asset(&request.into(), fee as f32, minconf, |psbt| {
-        wallet.sing_with_master_key(psbt)
+    let minconf = wallet.min_confirmations();
+    let mut reservation = signer::Reservation::default();
+    let send = wallet.send_asset(&data, fee as f32, minconf, |psbt| {
+        signer::sign_psbt(&plugin.state, psbt, &mut reservation)
     });
-    let send = send.map_err(|err| error!("{err}"))?;
+    let send = match send {
+        Ok(send) => send,
+        Err(err) => {
+            reservation.release(&plugin.state);
+            return Err(error!("{err}"));
+        }
+    };
     Ok(json::json!(send))
 }
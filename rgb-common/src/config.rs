@@ -0,0 +1,86 @@
+//! RGB plugin configuration
+//!
+//! Everything here can be tuned by the user, when a value is
+//! not specified we fall back to a default that depends on the network.
+use crate::bitcoin::Network;
+
+#[derive(Debug, Clone)]
+pub struct RGBConfig {
+    /// Electrum server used by the RGB wallet and to sync the master wallet.
+    pub electrum_url: Option<String>,
+    /// RGB proxy used to exchange the consignments.
+    pub proxy_url: Option<String>,
+    /// Minimum number of confirmations before an RGB allocation
+    /// is considered spendable.
+    pub min_confirmations: Option<u8>,
+    pub max_allocations_per_utxo: u32,
+    /// Size of the UTXOs created to hold the RGB allocations.
+    pub utxo_size_sat: u32,
+    /// Fee rate (sat/vB) used to build the RGB funding transaction.
+    pub funding_fee_rate: f32,
+    /// How often the pending transfers are refreshed, in seconds.
+    pub refresh_interval_secs: u64,
+    /// Allow the node to mint new assets, it is disabled by default
+    /// because the issuance is not something that a routing node does.
+    pub allow_issuance: bool,
+    /// Allow the plugin to run on mainnet, where the funds are real.
+    pub allow_mainnet: bool,
+}
+
+impl Default for RGBConfig {
+    fn default() -> Self {
+        Self {
+            electrum_url: None,
+            proxy_url: None,
+            min_confirmations: None,
+            max_allocations_per_utxo: 11,
+            utxo_size_sat: 32000,
+            funding_fee_rate: 1.1,
+            refresh_interval_secs: 60,
+            allow_issuance: false,
+            allow_mainnet: false,
+        }
+    }
+}
+
+impl RGBConfig {
+    pub fn electrum_url(&self, network: &Network) -> anyhow::Result<String> {
+        if let Some(ref url) = self.electrum_url {
+            return Ok(url.clone());
+        }
+        let url = match network {
+            Network::Bitcoin => "ssl://electrum.iriswallet.com:50003",
+            Network::Testnet => "ssl://electrum.iriswallet.com:50013",
+            Network::Signet => "ssl://mempool.space:60602",
+            Network::Regtest => "127.0.0.1:50001",
+            _ => anyhow::bail!("Network `{network}` not supported"),
+        };
+        Ok(url.to_owned())
+    }
+
+    pub fn proxy_url(&self, network: &Network) -> anyhow::Result<String> {
+        if let Some(ref url) = self.proxy_url {
+            return Ok(url.clone());
+        }
+        let url = match network {
+            Network::Bitcoin | Network::Testnet | Network::Signet => {
+                "rpcs://proxy.iriswallet.com/0.2/json-rpc"
+            }
+            Network::Regtest => "rpc://127.0.0.1:3000/json-rpc",
+            _ => anyhow::bail!("Network `{network}` not supported"),
+        };
+        Ok(url.to_owned())
+    }
+
+    /// Mainnet wants to be safe against reorgs, so it waits more blocks.
+    pub fn min_confirmations(&self, network: &Network) -> u8 {
+        if let Some(min_conf) = self.min_confirmations {
+            return min_conf;
+        }
+        match network {
+            Network::Bitcoin => 6,
+            Network::Regtest => 1,
+            _ => 3,
+        }
+    }
+}
@@ -1,10 +1,11 @@
 //! RGB Wallet mock
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use amplify::map;
 use bdk;
 use bdk::blockchain::ElectrumBlockchain;
 use bdk::electrum_client::Client;
@@ -21,24 +22,37 @@ use crate::bitcoin::secp256k1::Secp256k1;
 use crate::bitcoin::Network;
 use crate::bitcoin::{ScriptBuf, TxOut};
 use crate::bitcoin30::psbt::PartiallySignedTransaction as RgbPsbt;
+use crate::config::RGBConfig;
 use crate::core::contract::Operation;
+use crate::core::validation::Validity;
+use crate::core::Assign;
 use crate::core::SecretSeal;
 use crate::json;
 use crate::lib::utils::load_rgb_runtime;
 use crate::lib::wallet::RecipientData;
-use crate::lib::wallet::{AssetNIA, ReceiveData, Recipient};
+use crate::lib::wallet::{AssetCFA, AssetNIA, AssetUDA};
 use crate::lib::wallet::{DatabaseType, Online, Wallet as RgbWallet, WalletData};
+use crate::lib::wallet::{Invoice, ReceiveData, Recipient, Transfer};
 use crate::lib::BitcoinNetwork;
 use crate::rgb::persistence::Inventory;
 use crate::rgb::psbt::opret::OutputOpret;
 use crate::rgb::psbt::{PsbtDbc, RgbExt, RgbInExt};
+use crate::rgb::BlockchainResolver;
 use crate::rgb_manager::STATIC_BLINDING;
 use crate::std::containers::BuilderSeal;
-use crate::std::contract::GraphSeal;
+use crate::std::containers::{Bindle, Transfer as RgbTransfer};
+use crate::std::contract::{ContractId, GraphSeal};
 use crate::std::interface::TypedState;
 use crate::types;
 use crate::types::RgbInfo;
 
+/// Database file that rgb-lib creates inside the wallet directory.
+const RGB_LIB_DB_NAME: &str = "rgb_lib_db";
+
+/// Bitcoin amount of the output created to pay a witness
+/// receive, when the sender does not specify it.
+pub const WITNESS_AMOUNT_SAT: u64 = 1000;
+
 pub struct Wallet {
     path: String,
     pub network: BitcoinNetwork,
@@ -46,52 +60,54 @@ pub struct Wallet {
     pub online_wallet: Option<Online>,
     /// RGB proxy endpoint
     proxy_endpoint: String,
-    /// bdk wallet with the private key of cln
-    /// this is dangerus to keep because cln should sign
-    /// our  stuff too, but currently we use this approach
-    /// in the future we should find a solution.
-    ///
-    /// FIXME: please fix this
-    master_wallet: bdk::Wallet<bdk::database::MemoryDatabase>,
+    /// bdk wallet with the plugin private key, derived by cln
+    /// with `makesecret`. It signs the inputs of the RGB wallet,
+    /// the inputs of the cln wallet are signed by cln itself.
+    master_wallet: Mutex<bdk::Wallet<bdk::sled::Tree>>,
+    /// Electrum client used to sync the master wallet.
+    blockchain: ElectrumBlockchain,
+    /// Electrum server used to validate the consignments.
+    electrum_url: String,
+    min_confirmations: u8,
+    utxo_size_sat: u32,
+    /// On-chain balance read by the last [`Self::sync_btc_balance`],
+    /// `None` before the first sync.
+    btc_balance: Mutex<Option<json::Value>>,
 }
 
 impl Wallet {
-    pub fn new(network: &Network, xprv: ExtendedPrivKey, path: &str) -> anyhow::Result<Self> {
+    pub fn new(
+        network: &Network,
+        xprv: ExtendedPrivKey,
+        path: &str,
+        config: &RGBConfig,
+    ) -> anyhow::Result<Self> {
         let btc_network = BitcoinNetwork::from_str(&network.to_string())?;
+        let bdk_network = bdk::bitcoin::Network::from_str(&network.to_string())?;
+        // Keep the master wallet state on disk, so at every restart
+        // we do not need to sync the wallet from scratch.
+        let fingerprint = xprv.fingerprint(&Secp256k1::new());
+        let database =
+            bdk::sled::open(Path::new(path).join("bdk"))?.open_tree(fingerprint.to_string())?;
         let master_wallet = bdk::Wallet::new(
             bdk::template::Bip84(xprv, bdk::KeychainKind::External),
             Some(bdk::template::Bip84(xprv, bdk::KeychainKind::Internal)),
-            bdk::bitcoin::Network::Testnet,
-            bdk::database::MemoryDatabase::default(),
+            bdk_network,
+            database,
         )?;
-        let (url, proxy) = match network {
-            Network::Bitcoin => (None, None),
-            Network::Testnet => (
-                Some("ssl://electrum.iriswallet.com:50013"),
-                Some("rpcs://proxy.iriswallet.com/0.2/json-rpc"),
-            ),
-            Network::Signet => (None, None),
-            Network::Regtest => (
-                Some("127.0.0.1:50001"),
-                Some("rpc://127.0.0.1:3000/json-rpc"),
-            ),
-            _ => anyhow::bail!("Network `{network}` not supported"),
-        };
+        let url = config.electrum_url(network)?;
+        let proxy = config.proxy_url(network)?;
 
-        let (Some(url), Some(proxy)) = (url, proxy) else {
-            anyhow::bail!("Network `{network}` not supported by the plugin");
-        };
-
-        let blockchain = ElectrumBlockchain::from(Client::new(url)?);
-        master_wallet.sync(&blockchain, SyncOptions::default())?;
+        let blockchain = ElectrumBlockchain::from(Client::new(&url)?);
         // with rgb library tere is a new function for calculate the account key
         let account_privkey = Self::derive_account_xprv_from_mnemonic(btc_network, &xprv)?;
         let account_xpub = ExtendedPubKey::from_priv(&Secp256k1::new(), &account_privkey);
+        Self::check_orphan_wallets(path, &account_xpub.fingerprint().to_string())?;
         let mut wallet = RgbWallet::new(WalletData {
             data_dir: path.to_owned(),
             bitcoin_network: btc_network,
             database_type: DatabaseType::Sqlite,
-            max_allocations_per_utxo: 11,
+            max_allocations_per_utxo: config.max_allocations_per_utxo,
             pubkey: account_xpub.to_string().to_owned(),
             mnemonic: None,
             vanilla_keychain: None,
@@ -99,18 +115,72 @@ impl Wallet {
 
         let mut online_info = None;
         if !url.is_empty() {
-            online_info = Some(wallet.go_online(false, url.to_owned())?);
+            online_info = Some(wallet.go_online(false, url.clone())?);
         }
         Ok(Self {
             path: path.to_owned(),
-            proxy_endpoint: proxy.to_owned(),
+            proxy_endpoint: proxy,
             wallet: Arc::new(Mutex::new(wallet)),
             network: BitcoinNetwork::from_str(&network.to_string())?,
             online_wallet: online_info,
-            master_wallet,
+            master_wallet: Mutex::new(master_wallet),
+            blockchain,
+            electrum_url: url,
+            min_confirmations: config.min_confirmations(network),
+            utxo_size_sat: config.utxo_size_sat,
+            btc_balance: Mutex::new(None),
         })
     }
 
+    /// Refuse to start when `path` contains an RGB wallet that is not the
+    /// one of our key, e.g. a wallet created when the plugin key was derived
+    /// from the `hsm_secret`. Starting with a new key would silently hide
+    /// the assets of the old wallet.
+    fn check_orphan_wallets(path: &str, fingerprint: &str) -> anyhow::Result<()> {
+        let Ok(entries) = fs::read_dir(path) else {
+            return Ok(());
+        };
+        for entry in entries {
+            let wallet_dir = entry?.path();
+            let Some(name) = wallet_dir.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // rgb-lib names the wallet directory with the key fingerprint
+            if name == fingerprint || !wallet_dir.join(RGB_LIB_DB_NAME).exists() {
+                continue;
+            }
+            anyhow::bail!(
+                "found the RGB wallet `{}` created with a different key, the plugin key is now derived \
+                 by cln with `makesecret` and not from the `hsm_secret` anymore. Move the assets out \
+                 of the old wallet with a previous version of the plugin, or move the directory away \
+                 to start with a new wallet",
+                wallet_dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Sync the master wallet with the chain, the database already
+    /// contains the previous state so only the new transactions
+    /// are fetched.
+    pub fn sync_master_wallet(&self) -> anyhow::Result<()> {
+        self.master_wallet
+            .lock()
+            .unwrap()
+            .sync(&self.blockchain, SyncOptions::default())?;
+        Ok(())
+    }
+
+    pub fn is_mainnet(&self) -> bool {
+        self.network == BitcoinNetwork::Mainnet
+    }
+
+    /// Minimum number of confirmations before an RGB allocation
+    /// is considered spendable.
+    pub fn min_confirmations(&self) -> u8 {
+        self.min_confirmations
+    }
+
     pub fn path(&self) -> PathBuf {
         self.wallet.lock().unwrap().get_wallet_dir()
     }
@@ -135,7 +205,6 @@ impl Wallet {
         Ok(master_xprv.derive_priv(&Secp256k1::new(), &account_derivation_path)?)
     }
 
-    #[cfg(debug_assertions)]
     pub fn issue_asset_nia(
         &self,
         ticker: String,
@@ -156,6 +225,56 @@ impl Wallet {
         Ok(assert)
     }
 
+    /// Issue a Collectible Fungible Asset (RGB25), `file_path`
+    /// is the media attached to the contract.
+    pub fn issue_asset_cfa(
+        &self,
+        name: String,
+        description: Option<String>,
+        precision: u8,
+        amounts: Vec<u64>,
+        file_path: Option<String>,
+    ) -> anyhow::Result<AssetCFA> {
+        let Some(ref online) = self.online_wallet else {
+            anyhow::bail!("Wallet is not online");
+        };
+        let asset = self.wallet.lock().unwrap().issue_asset_cfa(
+            online.clone(),
+            name,
+            description,
+            precision,
+            amounts,
+            file_path,
+        )?;
+        Ok(asset)
+    }
+
+    /// Issue a Unique Digital Asset (RGB21), a single collectible with
+    /// its main media and the other attachments.
+    pub fn issue_asset_uda(
+        &self,
+        ticker: String,
+        name: String,
+        details: Option<String>,
+        precision: u8,
+        media_file_path: Option<String>,
+        attachments_file_paths: Vec<String>,
+    ) -> anyhow::Result<AssetUDA> {
+        let Some(ref online) = self.online_wallet else {
+            anyhow::bail!("Wallet is not online");
+        };
+        let asset = self.wallet.lock().unwrap().issue_asset_uda(
+            online.clone(),
+            ticker,
+            name,
+            details,
+            precision,
+            media_file_path,
+            attachments_file_paths,
+        )?;
+        Ok(asset)
+    }
+
     pub fn new_addr(&self) -> anyhow::Result<String> {
         let addr = self.wallet.lock().unwrap().get_address()?;
         Ok(addr)
@@ -175,15 +294,34 @@ impl Wallet {
             .online_wallet
             .clone()
             .ok_or(anyhow::anyhow!("Wallet is offline"))?;
+        if data.recipients.is_empty() {
+            anyhow::bail!("no recipients to send the assets");
+        }
+        let mut recipient_map: HashMap<String, Vec<Recipient>> = HashMap::new();
+        for recipient in &data.recipients {
+            let recipient_data = match recipient.witness_amount_sat {
+                Some(amount_sat) => RecipientData::WitnessData {
+                    script_buf: ScriptBuf::from_hex(&recipient.blinded_utxo)?,
+                    amount_sat,
+                    blinding: None,
+                },
+                None => RecipientData::BlindedUTXO(SecretSeal::from_str(&recipient.blinded_utxo)?),
+            };
+            recipient_map
+                .entry(recipient.asset_id.clone())
+                .or_default()
+                .push(Recipient {
+                    recipient_data,
+                    amount: recipient.amount,
+                    transport_endpoints: if recipient.transport_endpoints.is_empty() {
+                        vec![self.proxy_endpoint.clone()]
+                    } else {
+                        recipient.transport_endpoints.clone()
+                    },
+                });
+        }
+
         let wallet = self.wallet.lock().unwrap();
-        let seal = SecretSeal::from_str(&data.blinded_utxo)?;
-        let recipient_map = map! {
-            data.asset_id.clone() => vec![Recipient {
-                recipient_data: RecipientData::BlindedUTXO(seal),
-                amount: data.amount,
-                transport_endpoints: vec![self.proxy_endpoint.clone()],
-            }]
-        };
 
         let psbt = wallet.send_begin(
             online.clone(),
@@ -198,29 +336,114 @@ impl Wallet {
         Ok(sendresult)
     }
 
+    /// Build the recipient described by an RGB invoice, `amount` is
+    /// needed only when the invoice does not specify it.
+    pub fn recipient_from_invoice(
+        &self,
+        invoice: &str,
+        amount: Option<u64>,
+        witness_amount_sat: Option<u64>,
+    ) -> anyhow::Result<types::RGBSendRecipient> {
+        let invoice = Invoice::new(invoice.to_owned())?;
+        let data = invoice.invoice_data();
+        if data.network != self.network {
+            anyhow::bail!(
+                "invoice is for the network `{:?}` but we are on `{:?}`",
+                data.network,
+                self.network
+            );
+        }
+        if let Some(expiration) = data.expiration_timestamp {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+            if expiration < now {
+                anyhow::bail!("invoice expired at `{expiration}`");
+            }
+        }
+        let asset_id = data
+            .asset_id
+            .ok_or(anyhow::anyhow!("invoice without asset id is not supported"))?;
+        let amount = match (data.amount, amount) {
+            (Some(invoice_amount), Some(amount)) if invoice_amount != amount => {
+                anyhow::bail!("invoice asks for `{invoice_amount}` but we are sending `{amount}`")
+            }
+            (Some(amount), _) | (None, Some(amount)) => amount,
+            (None, None) => anyhow::bail!("`amount` is required by an invoice without amount"),
+        };
+        // A blinded receive has a secret seal as recipient id,
+        // otherwise the receiver asked for a witness receive.
+        let witness_amount_sat = match SecretSeal::from_str(&data.recipient_id) {
+            Ok(_) => None,
+            Err(_) => Some(witness_amount_sat.unwrap_or(WITNESS_AMOUNT_SAT)),
+        };
+        Ok(types::RGBSendRecipient {
+            asset_id,
+            amount,
+            blinded_utxo: data.recipient_id,
+            witness_amount_sat,
+            transport_endpoints: data.transport_endpoints,
+        })
+    }
+
+    /// Return the data to receive the assets, `duration_seconds` is
+    /// how long the invoice stays valid.
     pub fn new_blind_receive(
         &self,
         asset_id: Option<String>,
+        amount: Option<u64>,
+        duration_seconds: Option<u32>,
         min_confirmations: u8,
     ) -> anyhow::Result<ReceiveData> {
         let blind_receive = self.wallet.lock().unwrap().blind_receive(
             asset_id,
-            None,
-            None,
+            amount,
+            duration_seconds,
             vec![self.proxy_endpoint.clone()],
             min_confirmations,
         )?;
         Ok(blind_receive)
     }
 
+    /// Receive the assets on a new output of the sender transaction,
+    /// so we do not need to have a colored UTXO ready.
+    pub fn new_witness_receive(
+        &self,
+        asset_id: Option<String>,
+        amount: Option<u64>,
+        duration_seconds: Option<u32>,
+        min_confirmations: u8,
+    ) -> anyhow::Result<ReceiveData> {
+        let witness_receive = self.wallet.lock().unwrap().witness_receive(
+            asset_id,
+            amount,
+            duration_seconds,
+            vec![self.proxy_endpoint.clone()],
+            min_confirmations,
+        )?;
+        Ok(witness_receive)
+    }
+
+    /// Advance the pending transfers, fetching and ACKing the
+    /// consignments. Return true if some transfer changed.
+    pub fn refresh(&self) -> anyhow::Result<bool> {
+        let online = self
+            .online_wallet
+            .clone()
+            .ok_or(anyhow::anyhow!("Wallet is offline"))?;
+        let changed = self.wallet.lock().unwrap().refresh(online, None, vec![])?;
+        Ok(changed)
+    }
+
+    /// List the transfers of `asset_id`, or of every asset when `None`.
+    pub fn list_transfers(&self, asset_id: Option<String>) -> anyhow::Result<Vec<Transfer>> {
+        let transfers = self.wallet.lock().unwrap().list_transfers(asset_id)?;
+        Ok(transfers)
+    }
+
     /// Preallocate the UTXO assets on chain for RGB.
     pub fn create_utxos<F>(&self, fee_rate: f32, sign_psbt: F) -> anyhow::Result<()>
     where
         F: FnOnce(&mut bitcoin::psbt::PartiallySignedTransaction) -> anyhow::Result<()>,
     {
-        // FIXME: Mh, I should know why for this?
-        const UTXO_SIZE_SAT: u32 = 32000;
-
         let wallet_online = self
             .online_wallet
             .clone()
@@ -232,7 +455,7 @@ impl Wallet {
             wallet_online.clone(),
             false,
             Some(1),
-            Some(UTXO_SIZE_SAT),
+            Some(self.utxo_size_sat),
             fee_rate,
         )?;
 
@@ -245,41 +468,97 @@ impl Wallet {
         Ok(())
     }
 
-    pub fn get_btc_balance(&self) -> anyhow::Result<json::Value> {
+    /// Bitcoin that the RGB wallet can spend right now, without
+    /// touching the UTXOs that hold RGB allocations.
+    pub fn spendable_btc_sat(&self) -> anyhow::Result<u64> {
         let wallet = self.wallet.lock().unwrap();
         let balance = wallet.get_btc_balance(
             self.online_wallet
                 .clone()
                 .ok_or(anyhow::anyhow!("wallet is not online"))?,
         )?;
-        let cln = self.master_wallet.get_balance()?;
-        Ok(json::json!({
+        Ok(balance.vanilla.spendable)
+    }
+
+    /// Sync both the wallets with the chain and keep their balance,
+    /// this is slow so it is done by the refresh task.
+    pub fn sync_btc_balance(&self) -> anyhow::Result<()> {
+        let balance = self.wallet.lock().unwrap().get_btc_balance(
+            self.online_wallet
+                .clone()
+                .ok_or(anyhow::anyhow!("wallet is not online"))?,
+        )?;
+        self.sync_master_wallet()?;
+        let cln = self.master_wallet.lock().unwrap().get_balance()?;
+        *self.btc_balance.lock().unwrap() = Some(json::json!({
             "cln": cln,
             "rgb": balance,
-        }))
+        }));
+        Ok(())
+    }
+
+    /// Return the on-chain balance read by the last sync, without
+    /// touching the chain.
+    pub fn get_btc_balance(&self) -> Option<json::Value> {
+        self.btc_balance.lock().unwrap().clone()
     }
 
-    pub fn sing_with_master_key(
+    /// Sign the inputs of the psbt that belong to the RGB wallet,
+    /// and return true if the psbt is finalized.
+    pub fn sign_psbt(
         &self,
         psbt: &mut bitcoin::psbt::PartiallySignedTransaction,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<bool> {
         let sign_options = bdk::SignOptions {
             trust_witness_utxo: true,
             ..Default::default()
         };
+        let finalized = self
+            .master_wallet
+            .lock()
+            .unwrap()
+            .sign(psbt, sign_options)?;
+        Ok(finalized)
+    }
 
-        if !self.master_wallet.sign(psbt, sign_options)? {
-            anyhow::bail!("bdk is not able to sing with master key the psbt `{psbt}`");
+    /// Finalize the inputs that are signed but not finalized yet, e.g.
+    /// the ones signed by cln, and fail if an input is not finalized.
+    pub fn finalize_psbt(
+        &self,
+        psbt: &mut bitcoin::psbt::PartiallySignedTransaction,
+    ) -> anyhow::Result<()> {
+        use bdk::miniscript::psbt::PsbtExt;
+
+        let secp = Secp256k1::new();
+        let mut unfinalized = vec![];
+        for index in 0..psbt.inputs.len() {
+            let input = &psbt.inputs[index];
+            if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+                continue;
+            }
+            if let Err(err) = psbt.finalize_inp_mut(&secp, index) {
+                unfinalized.push(format!("input {index}: {err}"));
+            }
+        }
+        if !unfinalized.is_empty() {
+            anyhow::bail!(
+                "psbt is not finalized, {}: `{psbt}`",
+                unfinalized.join(", ")
+            );
         }
         Ok(())
     }
 
-    pub fn rgb_funding_complete(
+    pub fn rgb_funding_complete<F>(
         &self,
         recipient_map: HashMap<String, Vec<Recipient>>,
         fee_rate: f32,
         min_conf: u8,
-    ) -> anyhow::Result<bitcoin::psbt::PartiallySignedTransaction> {
+        sign_psbt: F,
+    ) -> anyhow::Result<bitcoin::psbt::PartiallySignedTransaction>
+    where
+        F: FnOnce(&mut bitcoin::psbt::PartiallySignedTransaction) -> anyhow::Result<()>,
+    {
         let wallet = self.wallet.lock().unwrap();
         let online = self
             .online_wallet
@@ -288,10 +567,97 @@ impl Wallet {
         let unsigned_psbt =
             wallet.send_begin(online.clone(), recipient_map, true, fee_rate, min_conf)?;
         let mut psbt = bitcoin::psbt::PartiallySignedTransaction::from_str(&unsigned_psbt)?;
-        self.sing_with_master_key(&mut psbt)?;
+        sign_psbt(&mut psbt)?;
         Ok(psbt)
     }
 
+    /// Broadcast the funding transaction signed by [`Self::rgb_funding_complete`]
+    /// and save the transfer inside the RGB wallet.
+    pub fn rgb_funding_end(
+        &self,
+        psbt: &bitcoin::psbt::PartiallySignedTransaction,
+    ) -> anyhow::Result<SendResult> {
+        let online = self
+            .online_wallet
+            .clone()
+            .ok_or(anyhow::anyhow!("Wallet not online"))?;
+        let result = self
+            .wallet
+            .lock()
+            .unwrap()
+            .send_end(online, psbt.to_string())?;
+        Ok(result)
+    }
+
+    /// Validate the consignment of a channel funded by the peer and
+    /// accept it inside the RGB runtime.
+    ///
+    /// Return the contract id and the amount assigned to the funding
+    /// output `funding_vout` of the funding transaction.
+    pub fn accept_funding_consignment(
+        &self,
+        consignment: &str,
+        funding_txid: &str,
+        funding_vout: u32,
+    ) -> anyhow::Result<(ContractId, u64)> {
+        let consignment_path = self
+            .path()
+            .join("transfers")
+            .join(funding_txid)
+            .join("consignment_in");
+        if let Some(parent) = consignment_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&consignment_path, base64::decode(consignment)?)?;
+        let consignment = Bindle::<RgbTransfer>::load(&consignment_path)
+            .map_err(|err| anyhow::anyhow!("invalid funding consignment: {err}"))?
+            .unbindle();
+        let contract_id = consignment.contract_id();
+
+        // The consignment is validated before it is imported,
+        // so an invalid one never ends up inside our stock.
+        let mut resolver =
+            BlockchainResolver::with(&self.electrum_url).map_err(|err| anyhow::anyhow!("{err}"))?;
+        let consignment = consignment
+            .validate(&mut resolver, !self.is_mainnet())
+            .unwrap_or_else(|consignment| consignment);
+        let status = consignment
+            .validation_status()
+            .ok_or(anyhow::anyhow!("funding consignment is not validated"))?;
+        // the funding transaction may be still in the mempool
+        if !matches!(
+            status.validity(),
+            Validity::Valid | Validity::UnminedTerminals
+        ) {
+            anyhow::bail!("invalid funding consignment: {:?}", status);
+        }
+        let mut runtime = load_rgb_runtime(self.path.clone().into(), self.network)?;
+        runtime
+            .runtime
+            .accept_transfer(consignment.clone(), &mut resolver, false)
+            .map_err(|err| anyhow::anyhow!("{err}"))?;
+
+        let mut amount = 0;
+        for bundle in consignment
+            .bundles
+            .iter()
+            .filter(|bundle| bundle.anchor.txid.to_string() == funding_txid)
+        {
+            for transition in bundle.bundle.known_transitions.values() {
+                for assignment in transition.assignments.values() {
+                    for fungible in assignment.as_fungible() {
+                        if let Assign::Revealed { seal, state, .. } = fungible {
+                            if seal.vout.into_u32() == funding_vout {
+                                amount += state.value.as_u64();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok((contract_id, amount))
+    }
+
     /// Given A PSBT we add the rgb information into it
     pub fn colored_funding(
         &self,
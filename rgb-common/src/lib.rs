@@ -1,17 +1,18 @@
-mod comm;
+pub mod config;
 mod internal_wallet;
-mod proxy;
+pub mod onion;
+pub mod proxy;
 mod rgb_manager;
-mod rgb_storage;
+pub mod rgb_storage;
 pub mod types;
 
-use lightning as ldk;
 use reqwest::blocking::Client as BlockingClient;
 
 pub use anyhow;
 pub use serde_json as json;
 // Re-exporting RGB dependencies under a single module.
 pub use bitcoin as bitcoin30;
+pub use internal_wallet::Wallet;
 pub use rgb;
 pub use rgb::interface::rgb20 as asset20;
 pub use rgb_core as core;
@@ -0,0 +1,260 @@
+//! RGB amount carried inside the onion of a payment.
+//!
+//! The payer builds the onion with `createonion` and adds to the payload
+//! of every hop a TLV record with the RGB amount of the HTLC, so a node
+//! that forwards the payment knows how much of the asset it is moving.
+//! The record type is odd, so a node that does not know it ignores it.
+//!
+//! See the onion payload format at
+//! https://github.com/lightning/bolts/blob/master/04-onion-routing.md#packet-structure
+
+/// TLV type of the RGB amount of the HTLC, encoded as a `tu64`.
+pub const RGB_AMOUNT_TLV_TYPE: u64 = 65537;
+
+const AMT_TO_FORWARD_TLV_TYPE: u64 = 2;
+const OUTGOING_CLTV_VALUE_TLV_TYPE: u64 = 4;
+const SHORT_CHANNEL_ID_TLV_TYPE: u64 = 6;
+const PAYMENT_DATA_TLV_TYPE: u64 = 8;
+
+/// The payload of a hop of the onion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HopPayload {
+    /// Amount in msat that the hop forwards, or receives if it is the last one
+    pub amt_to_forward: u64,
+    pub outgoing_cltv_value: u32,
+    /// Channel where the hop forwards the HTLC, `None` for the last hop
+    pub short_channel_id: Option<String>,
+    /// Payment secret and total amount in msat, only for the last hop
+    pub payment_data: Option<(String, u64)>,
+    /// RGB amount carried by the HTLC
+    pub rgb_amount: Option<u64>,
+}
+
+impl HopPayload {
+    /// Encode the payload as `createonion` wants it, a TLV
+    /// stream prefixed by its length, in hex.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut stream = vec![];
+        write_record(
+            &mut stream,
+            AMT_TO_FORWARD_TLV_TYPE,
+            &truncated(self.amt_to_forward),
+        );
+        write_record(
+            &mut stream,
+            OUTGOING_CLTV_VALUE_TLV_TYPE,
+            &truncated(self.outgoing_cltv_value as u64),
+        );
+        if let Some(ref short_channel_id) = self.short_channel_id {
+            let short_channel_id = parse_short_channel_id(short_channel_id)?;
+            write_record(
+                &mut stream,
+                SHORT_CHANNEL_ID_TLV_TYPE,
+                &short_channel_id.to_be_bytes(),
+            );
+        }
+        if let Some((ref payment_secret, total_msat)) = self.payment_data {
+            let mut value = hex::decode(payment_secret)?;
+            if value.len() != 32 {
+                anyhow::bail!("invalid payment secret `{payment_secret}`");
+            }
+            value.extend(truncated(total_msat));
+            write_record(&mut stream, PAYMENT_DATA_TLV_TYPE, &value);
+        }
+        if let Some(rgb_amount) = self.rgb_amount {
+            write_record(&mut stream, RGB_AMOUNT_TLV_TYPE, &truncated(rgb_amount));
+        }
+        let mut payload = vec![];
+        write_bigsize(&mut payload, stream.len() as u64);
+        payload.extend(stream);
+        Ok(hex::encode(payload))
+    }
+}
+
+/// Read the RGB amount from the hop `payload` that cln reports in the
+/// `htlc_accepted` hook, with or without the length prefix.
+pub fn decode_rgb_amount(payload: &str) -> anyhow::Result<Option<u64>> {
+    let payload = hex::decode(payload)?;
+    let mut stream = payload.as_slice();
+    let mut cursor = payload.as_slice();
+    if let Ok(len) = read_bigsize(&mut cursor) {
+        if len == cursor.len() as u64 {
+            stream = cursor;
+        }
+    }
+    let mut rgb_amount = None;
+    while !stream.is_empty() {
+        let record_type = read_bigsize(&mut stream)?;
+        let len = read_bigsize(&mut stream)? as usize;
+        if stream.len() < len {
+            anyhow::bail!("tlv record `{record_type}` is truncated");
+        }
+        let (value, rest) = stream.split_at(len);
+        stream = rest;
+        if record_type != RGB_AMOUNT_TLV_TYPE {
+            continue;
+        }
+        if value.len() > 8 || value.first() == Some(&0) {
+            anyhow::bail!("rgb amount `{}` is not a valid tu64", hex::encode(value));
+        }
+        rgb_amount = Some(value.iter().fold(0u64, |acc, byte| acc << 8 | *byte as u64));
+    }
+    Ok(rgb_amount)
+}
+
+/// Parse a short channel id in the `<block>x<tx>x<output>` format.
+fn parse_short_channel_id(short_channel_id: &str) -> anyhow::Result<u64> {
+    let parts = short_channel_id
+        .split('x')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow::anyhow!("invalid short channel id `{short_channel_id}`: {err}"))?;
+    match parts.as_slice() {
+        [block, tx, output] if *block < 1 << 24 && *tx < 1 << 24 && *output < 1 << 16 => {
+            Ok(block << 40 | tx << 16 | output)
+        }
+        _ => anyhow::bail!("invalid short channel id `{short_channel_id}`"),
+    }
+}
+
+/// Big endian encoding without the leading zeros.
+fn truncated(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    bytes[zeros..].to_vec()
+}
+
+fn write_record(stream: &mut Vec<u8>, record_type: u64, value: &[u8]) {
+    write_bigsize(stream, record_type);
+    write_bigsize(stream, value.len() as u64);
+    stream.extend(value);
+}
+
+fn write_bigsize(stream: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => stream.push(value as u8),
+        0xfd..=0xffff => {
+            stream.push(0xfd);
+            stream.extend((value as u16).to_be_bytes());
+        }
+        0x10000..=0xffffffff => {
+            stream.push(0xfe);
+            stream.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            stream.push(0xff);
+            stream.extend(value.to_be_bytes());
+        }
+    }
+}
+
+fn read_bigsize(stream: &mut &[u8]) -> anyhow::Result<u64> {
+    let (&prefix, rest) = stream
+        .split_first()
+        .ok_or(anyhow::anyhow!("bigsize is truncated"))?;
+    let len = match prefix {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        _ => {
+            *stream = rest;
+            return Ok(prefix as u64);
+        }
+    };
+    if rest.len() < len {
+        anyhow::bail!("bigsize is truncated");
+    }
+    let (value, rest) = rest.split_at(len);
+    *stream = rest;
+    Ok(value.iter().fold(0u64, |acc, byte| acc << 8 | *byte as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forward_payload() -> HopPayload {
+        HopPayload {
+            amt_to_forward: 1000,
+            outgoing_cltv_value: 123,
+            short_channel_id: Some("103x1x1".to_owned()),
+            payment_data: None,
+            rgb_amount: Some(10),
+        }
+    }
+
+    #[test]
+    fn encode_forward_payload() {
+        let payload = forward_payload().encode().unwrap();
+        assert_eq!(
+            payload,
+            // length, amt_to_forward, outgoing_cltv_value, short_channel_id, rgb amount
+            "18020203e804017b06080000670000010001fe00010001010a"
+        );
+    }
+
+    #[test]
+    fn encode_final_payload() {
+        let payload = HopPayload {
+            amt_to_forward: 1000,
+            outgoing_cltv_value: 123,
+            short_channel_id: None,
+            payment_data: Some(("11".repeat(32), 1000)),
+            rgb_amount: Some(10),
+        };
+        let encoded = payload.encode().unwrap();
+        assert!(
+            encoded.contains(&format!("0822{}03e8", "11".repeat(32))),
+            "{encoded}"
+        );
+        assert_eq!(decode_rgb_amount(&encoded).unwrap(), Some(10));
+
+        let payload = HopPayload {
+            payment_data: Some(("11".to_owned(), 1000)),
+            ..payload
+        };
+        assert!(payload.encode().is_err());
+    }
+
+    #[test]
+    fn decode_with_and_without_length() {
+        let payload = forward_payload().encode().unwrap();
+        assert_eq!(decode_rgb_amount(&payload).unwrap(), Some(10));
+        // without the length prefix
+        assert_eq!(decode_rgb_amount(&payload[2..]).unwrap(), Some(10));
+
+        let payload = HopPayload {
+            rgb_amount: None,
+            ..forward_payload()
+        };
+        assert_eq!(decode_rgb_amount(&payload.encode().unwrap()).unwrap(), None);
+        let payload = HopPayload {
+            rgb_amount: Some(u64::MAX),
+            ..forward_payload()
+        };
+        assert_eq!(
+            decode_rgb_amount(&payload.encode().unwrap()).unwrap(),
+            Some(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn decode_malformed() {
+        assert!(decode_rgb_amount("not hex").is_err());
+        // the rgb record claims more bytes than the stream has
+        assert!(decode_rgb_amount("fe00010001050a").is_err());
+        // a tu64 with a leading zero
+        assert!(decode_rgb_amount("fe0001000102000a").is_err());
+    }
+
+    #[test]
+    fn short_channel_id() {
+        assert_eq!(
+            parse_short_channel_id("103x1x1").unwrap(),
+            0x0000670000010001
+        );
+        assert!(parse_short_channel_id("103x1").is_err());
+        assert!(parse_short_channel_id("103x1xa").is_err());
+        assert!(parse_short_channel_id("16777216x1x1").is_err());
+    }
+}
@@ -1,12 +1,20 @@
 //! A module for operating an RGB HTTP JSON-RPC proxy
+//!
+//! See the protocol specification at
+//! https://github.com/RGB-Tools/rgb-http-json-rpc
 use core::str::FromStr;
 use core::time::Duration;
+use std::fmt;
 use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
 
 use amplify::s;
 use reqwest::blocking::multipart::Form;
 use reqwest::blocking::multipart::Part;
+use reqwest::blocking::RequestBuilder;
 use reqwest::header::CONTENT_TYPE;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use crate::bitcoin::Network;
@@ -14,17 +22,124 @@ use crate::BlockingClient;
 
 const JSON: &str = "application/json";
 const PROXY_TIMEOUT: u8 = 90;
+/// How many times we retry a call that failed for a transient error.
+const MAX_RETRIES: u32 = 3;
+/// Delay before the first retry, it is doubled at every retry.
+const RETRY_BASE_DELAY_MS: u64 = 500;
+/// Protocol versions that this client is able to speak.
+const SUPPORTED_PROTOCOL_VERSIONS: [&str; 1] = ["0.2"];
+
+// JSON-RPC error codes returned by the proxy, see the protocol specification.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const CANNOT_CHANGE_ACK: i64 = -101;
+const CANNOT_CHANGE_UPLOADED_FILE: i64 = -102;
+const MISSING_FILE: i64 = -103;
+const NOT_FOUND_CONSIGNMENT: i64 = -104;
+const NOT_FOUND_MEDIA: i64 = -105;
+
+/// Error returned by the RGB proxy client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyError {
+    /// The proxy is not reachable, or it is not able to answer right now.
+    Transport(String),
+    /// The proxy does not have the consignment or the media that we asked.
+    NotFound(String),
+    /// The ACK or the file is already uploaded with a different value.
+    AlreadySet(String),
+    /// The proxy refused our request because it is malformed.
+    InvalidRequest(String),
+    /// The proxy failed to handle our request.
+    Internal(String),
+    /// The proxy answered with a JSON-RPC error that we do not know.
+    JsonRpc { code: i64, message: String },
+    /// The proxy speaks a protocol version that we do not support.
+    UnsupportedVersion(String),
+    /// The proxy answered with something that we are not able to parse.
+    InvalidResponse(String),
+    /// We are not able to read the file to upload.
+    Io(String),
+}
+
+impl ProxyError {
+    /// Return true if the same call may succeed if we retry it later.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transport(_))
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "rgb proxy transport error: {err}"),
+            Self::NotFound(err) => write!(f, "rgb proxy not found: {err}"),
+            Self::AlreadySet(err) => write!(f, "rgb proxy refused the change: {err}"),
+            Self::InvalidRequest(err) => write!(f, "rgb proxy invalid request: {err}"),
+            Self::Internal(err) => write!(f, "rgb proxy internal error: {err}"),
+            Self::JsonRpc { code, message } => {
+                write!(f, "rgb proxy error `{code}`: {message}")
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "rgb proxy protocol version `{version}` is not supported")
+            }
+            Self::InvalidResponse(err) => write!(f, "rgb proxy invalid response: {err}"),
+            Self::Io(err) => write!(f, "rgb proxy io error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl From<reqwest::Error> for ProxyError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            return Self::InvalidResponse(err.to_string());
+        }
+        Self::Transport(err.to_string())
+    }
+}
+
+impl From<JsonRpcError> for ProxyError {
+    fn from(err: JsonRpcError) -> Self {
+        match err.code {
+            NOT_FOUND_CONSIGNMENT | NOT_FOUND_MEDIA => Self::NotFound(err.message),
+            CANNOT_CHANGE_ACK | CANNOT_CHANGE_UPLOADED_FILE => Self::AlreadySet(err.message),
+            PARSE_ERROR | METHOD_NOT_FOUND | INVALID_PARAMS | MISSING_FILE => {
+                Self::InvalidRequest(err.message)
+            }
+            INTERNAL_ERROR => Self::Internal(err.message),
+            code => Self::JsonRpc {
+                code,
+                message: err.message,
+            },
+        }
+    }
+}
+
+impl From<std::io::Error> for ProxyError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ConsignmentClient {
     inner: BlockingClient,
     #[allow(dead_code)]
     network: Network,
+    /// The proxy endpoint as it is used inside the RGB invoices,
+    /// e.g. `rpc://127.0.0.1:3000/json-rpc`.
     pub url: String,
+    /// The HTTP URL where we send the requests.
+    http_url: String,
+    /// The protocol version negotiated with the proxy.
+    protocol_version: OnceLock<String>,
 }
 
 impl ConsignmentClient {
-    pub fn new(network: &str) -> anyhow::Result<Self> {
+    pub fn new(network: &str, url: &str) -> anyhow::Result<Self> {
         let network = Network::from_str(network)?;
         let inner = BlockingClient::builder()
             .timeout(Duration::from_secs(PROXY_TIMEOUT as u64))
@@ -32,30 +147,155 @@ impl ConsignmentClient {
         Ok(Self {
             inner,
             network,
-            url: "".to_owned(),
+            url: url.to_owned(),
+            http_url: Self::http_url(url)?,
+            protocol_version: OnceLock::new(),
         })
     }
 
-    pub fn get_consignment(&self, consignment_id: &str) -> anyhow::Result<JsonRpcResponse<String>> {
+    /// Map the transport endpoint to the HTTP URL, `rpc://` is
+    /// plain HTTP while `rpcs://` is HTTPS.
+    fn http_url(url: &str) -> anyhow::Result<String> {
+        if let Some(url) = url.strip_prefix("rpcs://") {
+            return Ok(format!("https://{url}"));
+        }
+        if let Some(url) = url.strip_prefix("rpc://") {
+            return Ok(format!("http://{url}"));
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(url.to_owned());
+        }
+        anyhow::bail!("rgb proxy url `{url}` has an unsupported scheme")
+    }
+
+    /// Send the request built by `request`, retrying with
+    /// an exponential backoff when the error is transient.
+    ///
+    /// Only the idempotent methods are retried, a `*.post` may be
+    /// applied by the proxy even if we do not receive the answer.
+    ///
+    /// A `null` result is a valid answer for some methods, so
+    /// it is up to the caller to decide if it is an error.
+    fn call<R, F>(&self, method: &str, request: F) -> Result<Option<R>, ProxyError>
+    where
+        R: DeserializeOwned,
+        F: Fn() -> Result<RequestBuilder, ProxyError>,
+    {
+        let mut attempt = 0;
+        loop {
+            match self.try_call(&request) {
+                Err(err)
+                    if err.is_transient() && is_idempotent(method) && attempt < MAX_RETRIES =>
+                {
+                    let delay = RETRY_BASE_DELAY_MS * 2u64.pow(attempt);
+                    log::warn!("rgb proxy `{method}` failed, retry in {delay}ms: {err}");
+                    thread::sleep(Duration::from_millis(delay));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn try_call<R, F>(&self, request: &F) -> Result<Option<R>, ProxyError>
+    where
+        R: DeserializeOwned,
+        F: Fn() -> Result<RequestBuilder, ProxyError>,
+    {
+        let response = request()?.send()?;
+        let status = response.status();
+        if status.is_server_error() || status.as_u16() == 429 {
+            return Err(ProxyError::Transport(format!("http status `{status}`")));
+        }
+        response.json::<JsonRpcResponse<R>>()?.into_result()
+    }
+
+    fn json_call<P, R>(&self, method: &str, params: P) -> Result<Option<R>, ProxyError>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
         let body = JsonRpcRequest {
-            method: s!("consignment.get"),
+            method: method.to_owned(),
             jsonrpc: s!("2.0"),
-            id: None,
-            params: Some(BlindedUtxoParam {
-                blinded_utxo: consignment_id.to_owned(),
-            }),
+            id: Some(s!("1")),
+            params: Some(params),
         };
+        self.call(method, || {
+            Ok(self
+                .inner
+                .post(&self.http_url)
+                .header(CONTENT_TYPE, JSON)
+                .json(&body))
+        })
+    }
+
+    fn multipart_call<P, R>(
+        &self,
+        method: &str,
+        params: P,
+        file_path: &Path,
+    ) -> Result<Option<R>, ProxyError>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let params = serde_json::to_string(&params)
+            .map_err(|err| ProxyError::InvalidResponse(err.to_string()))?;
+        let file_name = file_path
+            .file_name()
+            .map(|filename| filename.to_string_lossy().into_owned())
+            .ok_or(ProxyError::InvalidResponse(format!(
+                "`{}` is not a file",
+                file_path.display()
+            )))?;
+        // The form owns the file, so we need to build it at every attempt.
+        self.call(method, || {
+            let file = Part::file(file_path)?.file_name(file_name.clone());
+            let form = Form::new()
+                .text("method", method.to_owned())
+                .text("jsonrpc", "2.0")
+                .text("id", "1")
+                .text("params", params.clone())
+                .part("file", file);
+            Ok(self.inner.post(&self.http_url).multipart(form))
+        })
+    }
+
+    /// Ask the proxy information about itself.
+    pub fn server_info(&self) -> Result<ServerInfo, ProxyError> {
+        self.json_call("server.info", ()).and_then(required)
+    }
 
-        // FIXME: add a URL for this
-        let url = format!("{}/TODO", self.url);
-        let resp = self
-            .inner
-            .post(format!("{url}"))
-            .header(CONTENT_TYPE, JSON)
-            .json(&body)
-            .send()?
-            .json::<JsonRpcResponse<String>>()?;
-        Ok(resp)
+    /// Check that the proxy speaks a protocol version that we support,
+    /// the check is done only once.
+    pub fn negotiate_version(&self) -> Result<String, ProxyError> {
+        if let Some(version) = self.protocol_version.get() {
+            return Ok(version.clone());
+        }
+        let info = self.server_info()?;
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&info.protocol_version.as_str()) {
+            return Err(ProxyError::UnsupportedVersion(info.protocol_version));
+        }
+        log::debug!(
+            "rgb proxy `{}` version `{}` with protocol `{}`",
+            self.url,
+            info.version,
+            info.protocol_version
+        );
+        let _ = self.protocol_version.set(info.protocol_version.clone());
+        Ok(info.protocol_version)
+    }
+
+    pub fn get_consignment(&self, recipient_id: &str) -> Result<ConsignmentInfo, ProxyError> {
+        self.negotiate_version()?;
+        self.json_call(
+            "consignment.get",
+            RecipientIdParam {
+                recipient_id: recipient_id.to_owned(),
+            },
+        )
+        .and_then(required)
     }
 
     pub fn post_consignment(
@@ -64,36 +304,73 @@ impl ConsignmentClient {
         recipient_id: String,
         txid: String,
         vout: Option<u32>,
-    ) -> anyhow::Result<()> {
-        let file_name = consignment_path
-            .file_name()
-            .map(|filename| filename.to_string_lossy().into_owned())
-            .unwrap();
-        let consignment_file = Part::file(consignment_path)?.file_name(file_name);
+    ) -> Result<bool, ProxyError> {
+        self.negotiate_version()?;
         let params = serde_json::json!({
             "recipient_id": recipient_id,
             "txid": txid,
             "vout": vout,
         });
+        self.multipart_call("consignment.post", params, consignment_path)
+            .and_then(required)
+    }
 
-        let form = Form::new()
-            .text("method", "consignment.post")
-            .text("jsonrpc", "2.0")
-            .text("id", "1")
-            .text("params", serde_json::to_string(&params)?)
-            .part("file", consignment_file);
+    /// Return the ACK of the receiver, `None` if the
+    /// receiver did not reply yet.
+    pub fn get_ack(&self, recipient_id: &str) -> Result<Option<bool>, ProxyError> {
+        self.negotiate_version()?;
+        self.json_call(
+            "ack.get",
+            RecipientIdParam {
+                recipient_id: recipient_id.to_owned(),
+            },
+        )
+    }
 
-        // FIXME: add a URL for this
-        let url = format!("{}/TODO", self.url);
-        self.inner
-            .post(format!("{url}"))
-            .header(CONTENT_TYPE, JSON)
-            .multipart(form)
-            .send()?
-            .json::<JsonRpcResponse<String>>()?;
+    pub fn post_ack(&self, recipient_id: &str, ack: bool) -> Result<bool, ProxyError> {
+        self.negotiate_version()?;
+        self.json_call(
+            "ack.post",
+            AckParam {
+                recipient_id: recipient_id.to_owned(),
+                ack,
+            },
+        )
+        .and_then(required)
+    }
 
-        Ok(())
+    /// Return the media in base64.
+    pub fn get_media(&self, attachment_id: &str) -> Result<String, ProxyError> {
+        self.negotiate_version()?;
+        self.json_call(
+            "media.get",
+            AttachmentIdParam {
+                attachment_id: attachment_id.to_owned(),
+            },
+        )
+        .and_then(required)
     }
+
+    pub fn post_media(&self, media_path: &Path, attachment_id: &str) -> Result<bool, ProxyError> {
+        self.negotiate_version()?;
+        self.multipart_call(
+            "media.post",
+            AttachmentIdParam {
+                attachment_id: attachment_id.to_owned(),
+            },
+            media_path,
+        )
+        .and_then(required)
+    }
+}
+
+/// Return true if calling `method` twice has the same effect as calling it once.
+fn is_idempotent(method: &str) -> bool {
+    method == "server.info" || method.ends_with(".get")
+}
+
+fn required<R>(result: Option<R>) -> Result<R, ProxyError> {
+    result.ok_or(ProxyError::InvalidResponse(s!("response without result")))
 }
 
 /// JSON-RPC Error
@@ -115,13 +392,130 @@ pub struct JsonRpcRequest<P> {
 /// JSON-RPC response
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct JsonRpcResponse<R> {
-    id: Option<String>,
+    id: Option<serde_json::Value>,
     pub(crate) result: Option<R>,
     pub(crate) error: Option<JsonRpcError>,
 }
 
-/// Blinded UTXO parameter
+impl<R> JsonRpcResponse<R> {
+    fn into_result(self) -> Result<Option<R>, ProxyError> {
+        match self.error {
+            Some(err) => Err(err.into()),
+            None => Ok(self.result),
+        }
+    }
+}
+
+/// Recipient ID parameter
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecipientIdParam {
+    recipient_id: String,
+}
+
+/// ACK parameter
 #[derive(Debug, Deserialize, Serialize)]
-pub struct BlindedUtxoParam {
-    blinded_utxo: String,
+pub struct AckParam {
+    recipient_id: String,
+    ack: bool,
+}
+
+/// Attachment ID parameter
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AttachmentIdParam {
+    attachment_id: String,
+}
+
+/// Answer of `server.info`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ServerInfo {
+    pub protocol_version: String,
+    pub version: String,
+    pub uptime: u64,
+}
+
+/// Answer of `consignment.get`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ConsignmentInfo {
+    /// The consignment encoded in base64
+    pub consignment: String,
+    pub txid: String,
+    pub vout: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response<R: DeserializeOwned>(body: &str) -> Result<Option<R>, ProxyError> {
+        serde_json::from_str::<JsonRpcResponse<R>>(body)
+            .unwrap()
+            .into_result()
+    }
+
+    fn error_response(code: i64) -> ProxyError {
+        let body = format!(
+            r#"{{"jsonrpc":"2.0","id":"1","error":{{"code":{code},"message":"boom"}}}}"#
+        );
+        response::<bool>(&body).unwrap_err()
+    }
+
+    #[test]
+    fn http_url_maps_the_scheme() {
+        let url = ConsignmentClient::http_url("rpc://127.0.0.1:3000/json-rpc").unwrap();
+        assert_eq!(url, "http://127.0.0.1:3000/json-rpc");
+        let url = ConsignmentClient::http_url("rpcs://proxy.rgbtools.org/json-rpc").unwrap();
+        assert_eq!(url, "https://proxy.rgbtools.org/json-rpc");
+        let url = ConsignmentClient::http_url("https://proxy.rgbtools.org/json-rpc").unwrap();
+        assert_eq!(url, "https://proxy.rgbtools.org/json-rpc");
+        assert!(ConsignmentClient::http_url("ftp://proxy.rgbtools.org").is_err());
+        assert!(ConsignmentClient::http_url("proxy.rgbtools.org").is_err());
+    }
+
+    #[test]
+    fn only_idempotent_methods_are_retried() {
+        assert!(is_idempotent("server.info"));
+        assert!(is_idempotent("consignment.get"));
+        assert!(is_idempotent("ack.get"));
+        assert!(is_idempotent("media.get"));
+        assert!(!is_idempotent("consignment.post"));
+        assert!(!is_idempotent("ack.post"));
+        assert!(!is_idempotent("media.post"));
+    }
+
+    #[test]
+    fn only_transport_errors_are_transient() {
+        assert!(ProxyError::Transport(s!("timeout")).is_transient());
+        assert!(!ProxyError::Internal(s!("boom")).is_transient());
+        assert!(!ProxyError::NotFound(s!("boom")).is_transient());
+    }
+
+    #[test]
+    fn json_rpc_errors_are_typed() {
+        assert_eq!(error_response(-104), ProxyError::NotFound(s!("boom")));
+        assert_eq!(error_response(-105), ProxyError::NotFound(s!("boom")));
+        assert_eq!(error_response(-101), ProxyError::AlreadySet(s!("boom")));
+        assert_eq!(error_response(-102), ProxyError::AlreadySet(s!("boom")));
+        assert_eq!(error_response(-103), ProxyError::InvalidRequest(s!("boom")));
+        assert_eq!(
+            error_response(-32602),
+            ProxyError::InvalidRequest(s!("boom"))
+        );
+        assert_eq!(error_response(-32603), ProxyError::Internal(s!("boom")));
+        assert_eq!(
+            error_response(-1),
+            ProxyError::JsonRpc {
+                code: -1,
+                message: s!("boom"),
+            }
+        );
+    }
+
+    #[test]
+    fn json_rpc_result_is_returned() {
+        let result = response::<bool>(r#"{"jsonrpc":"2.0","id":"1","result":true}"#);
+        assert_eq!(result.unwrap(), Some(true));
+        // `ack.get` answers `null` when the receiver did not reply yet
+        let result = response::<bool>(r#"{"jsonrpc":"2.0","id":"1","result":null}"#);
+        assert_eq!(result.unwrap(), None);
+    }
 }
@@ -1,35 +1,116 @@
 //! RGB Manager
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
 
 use bitcoin::bip32::ExtendedPrivKey;
 use bitcoin::Network;
+use rgb_lib::wallet::AssetCFA;
 use rgb_lib::wallet::AssetNIA;
+use rgb_lib::wallet::AssetUDA;
 use rgb_lib::wallet::Balance;
 use rgb_lib::wallet::Recipient;
 use rgb_lib::wallet::RecipientData;
 use rgbwallet::bitcoin;
 
+use crate::config::RGBConfig;
 use crate::internal_wallet::Wallet;
 use crate::json;
 use crate::proxy;
 use crate::rgb_storage as store;
-use crate::rgb_storage::RGBStorage;
+use crate::std::contract::ContractId;
 use crate::types;
+use crate::types::RgbChannelState;
+use crate::types::RgbFundingInfo;
 use crate::types::RgbInfo;
+use crate::types::{RgbForwardInfo, RgbHtlcDirection, RgbHtlcInfo, RgbPaymentInfo};
 
 /// Static blinding costant (will be removed in the future)
 /// See https://github.com/RGB-Tools/rust-lightning/blob/80497c4086beea490b56e5b8413b7f6d86f2c042/lightning/src/rgb_utils/mod.rs#L53
 pub const STATIC_BLINDING: u64 = 777;
 
+/// File inside the plugin directory with the user-defined aliases
+/// of the assets, a JSON object like `{ "usdt": "<contract id>" }`.
+pub const ASSET_ALIASES_FILE: &str = "asset_aliases.json";
+
+/// Attachment id of the media with the [`RgbFundingInfo`] of the
+/// channel funded by the transaction `funding_txid`.
+pub fn funding_info_attachment_id(funding_txid: &str) -> String {
+    format!("rgb-channel-{funding_txid}")
+}
+
+/// Limits enforced by the RGB schemas on the issued assets.
+const MAX_TICKER_LEN: usize = 8;
+const MAX_NAME_LEN: usize = 40;
+const MAX_PRECISION: u8 = 18;
+
 pub struct RGBManager {
     consignment_proxy: Arc<proxy::ConsignmentClient>,
     storage: Box<dyn store::RGBStorage>,
     wallet: Arc<Wallet>,
-    #[allow(dead_code)]
+    config: RGBConfig,
+    /// Wake up the refresh task before its timer.
+    refresh_trigger: OnceLock<mpsc::Sender<()>>,
     path: String,
 }
 
+/// Resolve `asset` to the contract id of an asset.
+///
+/// `asset` can be a contract id, an alias defined in [`ASSET_ALIASES_FILE`],
+/// or the ticker, the name or the `<name>/<ticker>` (e.g. `Tether/USDT`)
+/// of an asset known by the wallet. Aliases, names and tickers are case
+/// insensitive, and all together they must match a single asset.
+fn resolve_asset(
+    asset: &str,
+    aliases: &HashMap<String, String>,
+    assets: &[types::RgbAssetInfo],
+) -> anyhow::Result<String> {
+    let asset = asset.trim();
+    if ContractId::from_str(asset).is_ok() {
+        return Ok(asset.to_owned());
+    }
+    let mut matches = BTreeSet::new();
+    for (alias, contract_id) in aliases {
+        if !alias.trim().eq_ignore_ascii_case(asset) {
+            continue;
+        }
+        let contract_id = ContractId::from_str(contract_id.trim()).map_err(|err| {
+            anyhow::anyhow!("alias `{alias}` is not a valid contract id `{contract_id}`: {err}")
+        })?;
+        matches.insert(contract_id.to_string());
+    }
+    for info in assets {
+        let same_ticker = |ticker: &str| {
+            info.ticker
+                .as_ref()
+                .is_some_and(|value| value.eq_ignore_ascii_case(ticker.trim()))
+        };
+        let found = match asset.split_once('/') {
+            Some((name, ticker)) => {
+                info.name.eq_ignore_ascii_case(name.trim()) && same_ticker(ticker)
+            }
+            None => info.name.eq_ignore_ascii_case(asset) || same_ticker(asset),
+        };
+        if found {
+            matches.insert(info.asset_id.clone());
+        }
+    }
+    let mut matches = matches.into_iter().collect::<Vec<_>>();
+    match matches.len() {
+        0 => anyhow::bail!("asset `{asset}` is not known by the wallet"),
+        1 => Ok(matches.remove(0)),
+        _ => anyhow::bail!(
+            "asset `{asset}` is ambiguous, it matches `{}`",
+            matches.join("`, `")
+        ),
+    }
+}
+
 impl std::fmt::Debug for RGBManager {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "RGB manager struct {{ .. }}")
@@ -41,21 +122,64 @@ impl RGBManager {
         root_dir: &str,
         master_xprv: &ExtendedPrivKey,
         network: &str,
+        storage: Box<dyn store::RGBStorage>,
+        config: RGBConfig,
     ) -> anyhow::Result<Self> {
-        let storage = Box::new(store::InMemoryStorage::new()?);
-        let client = proxy::ConsignmentClient::new(network)?;
+        // The only network guard, the plugin is never initialized
+        // on mainnet without the explicit opt-in.
         let network = Network::from_str(network)?;
+        if network == Network::Bitcoin {
+            if !config.allow_mainnet {
+                anyhow::bail!("running on mainnet requires the `rgb-allow-mainnet` option");
+            }
+            log::warn!("rgb plugin is running on mainnet");
+        }
+        let client =
+            proxy::ConsignmentClient::new(&network.to_string(), &config.proxy_url(&network)?)?;
 
-        let wallet = Wallet::new(&network, *master_xprv, root_dir)?;
-        // FIXME: setting up the correct proxy client URL
+        let wallet = Wallet::new(&network, *master_xprv, root_dir, &config)?;
+        Self::recover_rgb_info(storage.as_ref())?;
         Ok(Self {
             consignment_proxy: Arc::new(client),
             wallet: Arc::new(wallet),
+            config,
+            refresh_trigger: OnceLock::new(),
             path: root_dir.to_owned(),
             storage,
         })
     }
 
+    /// Finish the renames of the channel info interrupted by a crash.
+    ///
+    /// `rename_pending_rgb_info` and `confirm_rgb_info` update the record
+    /// before moving it, so a record stored under the wrong key knows
+    /// where it should be.
+    fn recover_rgb_info(storage: &dyn store::RGBStorage) -> anyhow::Result<()> {
+        for (key, value) in storage.list("rgb/pending/channel")? {
+            let info: RgbInfo = json::from_str(&value)?;
+            let is_pending = info.state == RgbChannelState::Opening;
+            let expected_key = store::derive_channel_db_key(&info.channel_id, is_pending)?;
+            if key == expected_key {
+                continue;
+            }
+            log::info!("recovering rgb channel info `{key}` to `{expected_key}`");
+            if storage.get(&expected_key)?.is_some() {
+                storage.delete(&key)?;
+            } else {
+                storage.rename(&key, &expected_key)?;
+            }
+        }
+        for info in storage.list_rgb_channel_info(false)? {
+            let Some(ref short_channel_id) = info.short_channel_id else {
+                continue;
+            };
+            if storage.get_rgb_channel_id(short_channel_id)?.is_none() {
+                storage.write_rgb_channel_id(short_channel_id, &info.channel_id)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn wallet(&self) -> Arc<Wallet> {
         self.wallet.clone()
     }
@@ -64,7 +188,75 @@ impl RGBManager {
         self.consignment_proxy.clone()
     }
 
-    #[cfg(debug_assertions)]
+    pub fn config(&self) -> &RGBConfig {
+        &self.config
+    }
+
+    pub fn is_mainnet(&self) -> bool {
+        self.wallet.is_mainnet()
+    }
+
+    fn asset_aliases(&self) -> anyhow::Result<HashMap<String, String>> {
+        let path = Path::new(&self.path).join(ASSET_ALIASES_FILE);
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+        let aliases = fs::read_to_string(&path)?;
+        json::from_str(&aliases)
+            .map_err(|err| anyhow::anyhow!("invalid asset aliases `{}`: {err}", path.display()))
+    }
+
+    /// Resolve `asset` to the contract id of an asset, see [`resolve_asset`].
+    pub fn resolve_asset_id(&self, asset: &str) -> anyhow::Result<String> {
+        if ContractId::from_str(asset.trim()).is_ok() {
+            return Ok(asset.trim().to_owned());
+        }
+        resolve_asset(asset, &self.asset_aliases()?, &self.list_assets()?)
+    }
+
+    /// Check that the node is allowed to issue, and that the asset
+    /// is something that RGB will accept before touching the wallet.
+    fn check_issuance(
+        &self,
+        ticker: Option<&str>,
+        name: &str,
+        precision: u8,
+        amounts: &[u64],
+    ) -> anyhow::Result<u64> {
+        if !self.config.allow_issuance {
+            anyhow::bail!(
+                "asset issuance is disabled, enable it with the `rgb-allow-issuance` option"
+            );
+        }
+        if let Some(ticker) = ticker {
+            let valid = (1..=MAX_TICKER_LEN).contains(&ticker.len())
+                && ticker.starts_with(|c: char| c.is_ascii_uppercase())
+                && ticker
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+            if !valid {
+                anyhow::bail!(
+                    "invalid ticker `{ticker}`, it must be 1 to {MAX_TICKER_LEN} uppercase letters or digits starting with a letter"
+                );
+            }
+        }
+        if name.trim().is_empty() || name.len() > MAX_NAME_LEN {
+            anyhow::bail!("invalid name `{name}`, it must be 1 to {MAX_NAME_LEN} characters");
+        }
+        if precision > MAX_PRECISION {
+            anyhow::bail!("invalid precision `{precision}`, the maximum is {MAX_PRECISION}");
+        }
+        if amounts.is_empty() || amounts.contains(&0) {
+            anyhow::bail!("the amounts to issue must be non empty and greater than zero");
+        }
+        amounts
+            .iter()
+            .try_fold(0u64, |total, amount| total.checked_add(*amount))
+            .ok_or(anyhow::anyhow!(
+                "the sum of the amounts `{amounts:?}` overflows"
+            ))
+    }
+
     pub fn issue_asset_nia(
         &self,
         ticker: String,
@@ -72,8 +264,63 @@ impl RGBManager {
         precision: u8,
         amounts: Vec<u64>,
     ) -> anyhow::Result<AssetNIA> {
-        self.wallet
-            .issue_asset_nia(ticker, name, precision, amounts)
+        let supply = self.check_issuance(Some(&ticker), &name, precision, &amounts)?;
+        let asset = self
+            .wallet
+            .issue_asset_nia(ticker, name, precision, amounts)?;
+        log::info!(
+            "issued NIA asset `{}` ticker `{}` with supply {supply}",
+            asset.asset_id,
+            asset.ticker
+        );
+        Ok(asset)
+    }
+
+    pub fn issue_asset_cfa(
+        &self,
+        name: String,
+        description: Option<String>,
+        precision: u8,
+        amounts: Vec<u64>,
+        file_path: Option<String>,
+    ) -> anyhow::Result<AssetCFA> {
+        let supply = self.check_issuance(None, &name, precision, &amounts)?;
+        let asset =
+            self.wallet
+                .issue_asset_cfa(name, description, precision, amounts, file_path)?;
+        log::info!(
+            "issued CFA asset `{}` name `{}` with supply {supply}",
+            asset.asset_id,
+            asset.name
+        );
+        Ok(asset)
+    }
+
+    /// A UDA is a single collectible, so its supply is always 1.
+    pub fn issue_asset_uda(
+        &self,
+        ticker: String,
+        name: String,
+        details: Option<String>,
+        precision: u8,
+        media_file_path: Option<String>,
+        attachments_file_paths: Vec<String>,
+    ) -> anyhow::Result<AssetUDA> {
+        self.check_issuance(Some(&ticker), &name, precision, &[1])?;
+        let asset = self.wallet.issue_asset_uda(
+            ticker,
+            name,
+            details,
+            precision,
+            media_file_path,
+            attachments_file_paths,
+        )?;
+        log::info!(
+            "issued UDA asset `{}` ticker `{}`",
+            asset.asset_id,
+            asset.ticker
+        );
+        Ok(asset)
     }
 
     pub fn assert_balance(&self, asset_id: String) -> anyhow::Result<Balance> {
@@ -86,29 +333,429 @@ impl RGBManager {
         Ok(balance)
     }
 
-    pub fn onchain_balance(&self) -> anyhow::Result<json::Value> {
-        let balance = self.wallet.get_btc_balance()?;
-        Ok(balance)
+    /// Advance the pending transfers of the wallet, and check if the
+    /// peers accepted the consignments of the channels that we funded.
+    ///
+    /// Return true if something changed.
+    pub fn refresh(&self) -> anyhow::Result<bool> {
+        // the balance RPC reads what we sync here
+        if let Err(err) = self.wallet.sync_btc_balance() {
+            log::warn!("failing to sync the on-chain balance: {err}");
+        }
+        let mut changed = self.wallet.refresh()?;
+        for info in self.storage.list_rgb_channel_info(false)? {
+            if info.funding_ack.is_some() || info.state == RgbChannelState::Closed {
+                continue;
+            }
+            let Some(ref funding_txid) = info.funding_txid else {
+                continue;
+            };
+            let ack = match self.consignment_proxy.get_ack(funding_txid) {
+                Ok(Some(ack)) => ack,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::warn!("failing to get the funding ack of `{funding_txid}`: {err}");
+                    continue;
+                }
+            };
+            if !ack {
+                log::error!(
+                    "peer refused the funding consignment of the channel `{}`",
+                    info.channel_id
+                );
+            }
+            self.storage
+                .update_rgb_info(&info.channel_id, false, &mut |info| {
+                    info.funding_ack = Some(ack);
+                    Ok(())
+                })?;
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Start a background thread that refreshes the pending transfers
+    /// every `interval`, or earlier when [`Self::trigger_refresh`] is called.
+    pub fn start_refresh_task(self: &Arc<Self>, interval: Duration) -> anyhow::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.refresh_trigger
+            .set(sender)
+            .map_err(|_| anyhow::anyhow!("rgb refresh task is already running"))?;
+        // The task must not keep the manager alive.
+        let manager = Arc::downgrade(self);
+        thread::Builder::new()
+            .name("rgb-refresh".to_owned())
+            .spawn(move || loop {
+                match receiver.recv_timeout(interval) {
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                // many triggers at the same time need a single refresh
+                while receiver.try_recv().is_ok() {}
+                let Some(manager) = manager.upgrade() else {
+                    return;
+                };
+                match manager.refresh() {
+                    Ok(changed) => log::debug!("rgb refresh completed, changes: {changed}"),
+                    Err(err) => log::warn!("rgb refresh failed: {err}"),
+                }
+            })?;
+        // The first refresh fills the cached state at the start.
+        self.trigger_refresh();
+        Ok(())
+    }
+
+    /// Ask the refresh task to run now, e.g. because there is a new block.
+    pub fn trigger_refresh(&self) {
+        if let Some(trigger) = self.refresh_trigger.get() {
+            let _ = trigger.send(());
+        }
+    }
+
+    /// List every asset known by the wallet, with the amount that is
+    /// locked inside the open RGB channels and the pending ones.
+    pub fn list_assets(&self) -> anyhow::Result<Vec<types::RgbAssetInfo>> {
+        let channels_amount = |pending: bool| -> anyhow::Result<HashMap<String, u64>> {
+            let mut amounts: HashMap<String, u64> = HashMap::new();
+            for info in self.storage.list_rgb_channel_info(pending)? {
+                if info.state == RgbChannelState::Closed {
+                    continue;
+                }
+                *amounts.entry(info.contract_id.to_string()).or_default() += info.local_rgb_amount;
+            }
+            Ok(amounts)
+        };
+        let open_amount = channels_amount(false)?;
+        let pending_amount = channels_amount(true)?;
+        let channels_local_amount =
+            |asset_id: &str| open_amount.get(asset_id).copied().unwrap_or_default();
+        let pending_channels_local_amount =
+            |asset_id: &str| pending_amount.get(asset_id).copied().unwrap_or_default();
+
+        let assets = self.wallet.wallet.lock().unwrap().list_assets(vec![])?;
+        let mut result = vec![];
+        for asset in assets.nia.unwrap_or_default() {
+            result.push(types::RgbAssetInfo {
+                channels_local_amount: channels_local_amount(&asset.asset_id),
+                pending_channels_local_amount: pending_channels_local_amount(&asset.asset_id),
+                asset_id: asset.asset_id,
+                schema: "NIA".to_owned(),
+                ticker: Some(asset.ticker),
+                name: asset.name,
+                precision: asset.precision,
+                issued_supply: asset.issued_supply,
+                balance: asset.balance,
+            });
+        }
+        for asset in assets.cfa.unwrap_or_default() {
+            result.push(types::RgbAssetInfo {
+                channels_local_amount: channels_local_amount(&asset.asset_id),
+                pending_channels_local_amount: pending_channels_local_amount(&asset.asset_id),
+                asset_id: asset.asset_id,
+                schema: "CFA".to_owned(),
+                ticker: None,
+                name: asset.name,
+                precision: asset.precision,
+                issued_supply: asset.issued_supply,
+                balance: asset.balance,
+            });
+        }
+        for asset in assets.uda.unwrap_or_default() {
+            result.push(types::RgbAssetInfo {
+                channels_local_amount: channels_local_amount(&asset.asset_id),
+                pending_channels_local_amount: pending_channels_local_amount(&asset.asset_id),
+                asset_id: asset.asset_id,
+                schema: "UDA".to_owned(),
+                ticker: Some(asset.ticker),
+                name: asset.name,
+                precision: asset.precision,
+                issued_supply: asset.issued_supply,
+                balance: asset.balance,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Return the on-chain balance synced by the last refresh, if any.
+    pub fn onchain_balance(&self) -> Option<json::Value> {
+        self.wallet.get_btc_balance()
     }
 
     pub fn add_rgb_info(&self, info: &RgbInfo, pending: bool) -> anyhow::Result<()> {
         self.storage.write_rgb_info(&info.channel_id, pending, info)
     }
 
+    pub fn remove_rgb_info(&self, channel_id: &str, pending: bool) -> anyhow::Result<()> {
+        self.storage.remove_rgb_info(channel_id, pending)
+    }
+
+    /// Move the pending channel info stored under the temporary channel id
+    /// to the final channel id, the channel stays pending until it is locked in.
+    pub fn rename_pending_rgb_info(
+        &self,
+        temporary_channel_id: &str,
+        channel_id: &str,
+    ) -> anyhow::Result<RgbInfo> {
+        // The record is updated in place and then moved, so if we crash in
+        // the middle the record still says where it belongs and
+        // `recover_rgb_info` can finish the job at the next start.
+        let mut renamed = None;
+        self.storage
+            .update_rgb_info(temporary_channel_id, true, &mut |info| {
+                info.channel_id = channel_id.to_owned();
+                renamed = Some(info.clone());
+                Ok(())
+            })?;
+        self.storage.rename(
+            &store::derive_channel_db_key(temporary_channel_id, true)?,
+            &store::derive_channel_db_key(channel_id, true)?,
+        )?;
+        renamed.ok_or(anyhow::anyhow!(
+            "pending rgb channel `{temporary_channel_id}` is not found"
+        ))
+    }
+
+    /// Promote the pending channel info to a confirmed one, this
+    /// should be called when cln report the channel as open.
+    pub fn confirm_rgb_info(
+        &self,
+        channel_id: &str,
+        short_channel_id: Option<String>,
+    ) -> anyhow::Result<RgbInfo> {
+        let mut confirmed = None;
+        self.storage
+            .update_rgb_info(channel_id, true, &mut |info| {
+                info.state = RgbChannelState::Open;
+                info.short_channel_id = short_channel_id.clone();
+                confirmed = Some(info.clone());
+                Ok(())
+            })?;
+        self.storage.rename(
+            &store::derive_channel_db_key(channel_id, true)?,
+            &store::derive_channel_db_key(channel_id, false)?,
+        )?;
+        if let Some(ref short_channel_id) = short_channel_id {
+            self.storage
+                .write_rgb_channel_id(short_channel_id, channel_id)?;
+        }
+        confirmed.ok_or(anyhow::anyhow!(
+            "pending rgb channel `{channel_id}` is not found"
+        ))
+    }
+
+    /// Accept a channel funded by the peer with the output `funding_vout` of
+    /// the transaction `funding_txid`, and store its pending channel info.
+    ///
+    /// The funder posts the consignment of the funding transaction to the proxy,
+    /// we validate it and reply with an ACK. Return `None` if there is no
+    /// consignment, because the channel is not an RGB channel.
+    pub fn accept_rgb_funding(
+        &self,
+        channel_id: &str,
+        funding_txid: &str,
+        funding_vout: u32,
+    ) -> anyhow::Result<Option<RgbInfo>> {
+        if self.is_channel_rgb(channel_id, true)? || self.is_channel_rgb(channel_id, false)? {
+            return Ok(None);
+        }
+        let consignment = match self.consignment_proxy.get_consignment(funding_txid) {
+            Ok(consignment) => consignment,
+            Err(proxy::ProxyError::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let funding_info = self
+            .consignment_proxy
+            .get_media(&funding_info_attachment_id(funding_txid))?;
+        let funding_info: RgbFundingInfo = json::from_slice(&base64::decode(funding_info)?)?;
+
+        let accepted = self
+            .wallet
+            .accept_funding_consignment(&consignment.consignment, funding_txid, funding_vout)
+            .and_then(|(contract_id, amount)| {
+                if contract_id != funding_info.contract_id {
+                    anyhow::bail!(
+                        "funding consignment is for `{contract_id}`, `{}` expected",
+                        funding_info.contract_id
+                    );
+                }
+                if amount < funding_info.push_rgb_amount {
+                    anyhow::bail!(
+                        "funding consignment assigns `{amount}`, less than the pushed `{}`",
+                        funding_info.push_rgb_amount
+                    );
+                }
+                Ok(amount)
+            });
+        let amount = match accepted {
+            Ok(amount) => amount,
+            Err(err) => {
+                // the funder sees the NACK at its next refresh
+                if let Err(nack_err) = self.consignment_proxy.post_ack(funding_txid, false) {
+                    log::error!(
+                        "failing to refuse the funding consignment of `{funding_txid}`: {nack_err}"
+                    );
+                }
+                anyhow::bail!("refusing the rgb funding of the channel `{channel_id}`: {err}");
+            }
+        };
+        let info = RgbInfo {
+            channel_id: channel_id.to_owned(),
+            short_channel_id: None,
+            contract_id: funding_info.contract_id,
+            local_rgb_amount: funding_info.push_rgb_amount,
+            remote_rgb_amount: amount - funding_info.push_rgb_amount,
+            state: RgbChannelState::Opening,
+            funding_txid: Some(funding_txid.to_owned()),
+            funding_ack: Some(true),
+        };
+        self.add_rgb_info(&info, true)?;
+        self.consignment_proxy.post_ack(funding_txid, true)?;
+        log::info!(
+            "accepted the rgb funding of the channel `{channel_id}`: {:?}",
+            info
+        );
+        Ok(Some(info))
+    }
+
+    /// Update the state of a confirmed RGB channel.
+    pub fn update_rgb_channel_state(
+        &self,
+        channel_id: &str,
+        state: RgbChannelState,
+    ) -> anyhow::Result<()> {
+        self.storage
+            .update_rgb_info(channel_id, false, &mut |info| {
+                info.state = state;
+                if matches!(state, RgbChannelState::Closing | RgbChannelState::Closed) {
+                    log::info!(
+                        "rgb channel `{channel_id}` is {:?} with final allocations local `{}` remote `{}` of `{}`",
+                        state,
+                        info.local_rgb_amount,
+                        info.remote_rgb_amount,
+                        info.contract_id
+                    );
+                }
+                Ok(())
+            })
+    }
+
+    pub fn list_rgb_channel_info(&self, pending: bool) -> anyhow::Result<Vec<RgbInfo>> {
+        self.storage.list_rgb_channel_info(pending)
+    }
+
+    pub fn is_channel_rgb(&self, channel_id: &str, pending: bool) -> anyhow::Result<bool> {
+        self.storage.is_channel_rgb(channel_id, pending)
+    }
+
+    /// Return the channel id of the RGB channel with the `short_channel_id`, if any.
+    pub fn rgb_channel_id(&self, short_channel_id: &str) -> anyhow::Result<Option<String>> {
+        self.storage.get_rgb_channel_id(short_channel_id)
+    }
+
+    pub fn rgb_channel_info(&self, channel_id: &str) -> anyhow::Result<RgbInfo> {
+        self.storage.get_rgb_channel_info(channel_id)
+    }
+
+    pub fn add_rgb_payment_info(
+        &self,
+        payment_hash: &str,
+        info: &RgbPaymentInfo,
+    ) -> anyhow::Result<()> {
+        self.storage.write_rgb_payment_info(payment_hash, info)
+    }
+
+    /// Return the RGB payment info for the `payment_hash`, if any.
+    pub fn rgb_payment_info(&self, payment_hash: &str) -> anyhow::Result<Option<RgbPaymentInfo>> {
+        if !self.storage.is_payment_rgb(payment_hash)? {
+            return Ok(None);
+        }
+        let info = self.storage.get_rgb_payment_info(payment_hash)?;
+        Ok(Some(info))
+    }
+
+    /// Track an HTLC that carries an RGB amount, the channel
+    /// balance are updated only when the HTLC is settled.
+    pub fn add_rgb_htlc(&self, payment_hash: &str, info: &RgbHtlcInfo) -> anyhow::Result<()> {
+        self.storage.write_rgb_htlc_info(payment_hash, info)
+    }
+
+    /// Move the RGB amount of a settled HTLC inside the channel.
+    pub fn settle_rgb_htlc(&self, payment_hash: &str) -> anyhow::Result<()> {
+        let Some(htlc) = self.storage.take_rgb_htlc_info(payment_hash)? else {
+            return Ok(());
+        };
+        let (offered, received) = match htlc.direction {
+            RgbHtlcDirection::Offered => (htlc.amount, 0),
+            RgbHtlcDirection::Received => (0, htlc.amount),
+        };
+        self.storage
+            .update_rgb_channel_amount(&htlc.channel_id, offered, received)?;
+        log::info!(
+            "rgb htlc `{payment_hash}` settled on channel `{}`: {:?}",
+            htlc.channel_id,
+            htlc
+        );
+        Ok(())
+    }
+
+    /// Forget a failed HTLC, the channel balance is not changed.
+    pub fn fail_rgb_htlc(&self, payment_hash: &str) -> anyhow::Result<()> {
+        let _ = self.storage.take_rgb_htlc_info(payment_hash)?;
+        Ok(())
+    }
+
+    /// Track an HTLC that we forward between two RGB channels, the
+    /// channel balances are updated only when the HTLC is settled.
+    pub fn add_rgb_forward(&self, payment_hash: &str, info: &RgbForwardInfo) -> anyhow::Result<()> {
+        self.storage.write_rgb_forward_info(payment_hash, info)
+    }
+
+    /// Move the RGB amount of a settled forward, we receive it in the
+    /// incoming channel and we give it away in the outgoing one.
+    pub fn settle_rgb_forward(&self, payment_hash: &str) -> anyhow::Result<()> {
+        let Some(forward) = self.storage.take_rgb_forward_info(payment_hash)? else {
+            return Ok(());
+        };
+        self.storage
+            .update_rgb_channel_amount(&forward.in_channel_id, 0, forward.amount)?;
+        self.storage
+            .update_rgb_channel_amount(&forward.out_channel_id, forward.amount, 0)?;
+        log::info!("rgb forward `{payment_hash}` settled: {:?}", forward);
+        Ok(())
+    }
+
+    /// Forget a failed forward, the channel balances are not changed.
+    pub fn fail_rgb_forward(&self, payment_hash: &str) -> anyhow::Result<()> {
+        let _ = self.storage.take_rgb_forward_info(payment_hash)?;
+        Ok(())
+    }
+
     /// Modify the funding transaction before sign it with the node signer.
-    pub fn build_rgb_funding_transaction(
+    pub fn build_rgb_funding_transaction<F>(
         &self,
         rgb_info: &RgbInfo,
         scriptpubkey: bitcoin::ScriptBuf,
+        amount_sat: u64,
         fee_rate: f32,
         min_conf: u8,
-    ) -> anyhow::Result<bitcoin::psbt::PartiallySignedTransaction> {
+        sign_psbt: F,
+    ) -> anyhow::Result<bitcoin::psbt::PartiallySignedTransaction>
+    where
+        F: FnOnce(&mut bitcoin::psbt::PartiallySignedTransaction) -> anyhow::Result<()>,
+    {
         // Step 1: get the rgb info https://github.com/RGB-Tools/rgb-lightning-node/blob/master/src/ldk.rs#L328
         //let mut info = self.storage.get_rgb_channel_info_pending(&channel_id)?;
         //info.channel_id = channel_id;
 
         // Step 2: Modify the psbt and start sending with the rgb wallet
-        let psbt = self.prepare_rgb_tx(&rgb_info, scriptpubkey, fee_rate, min_conf)?;
+        let psbt = self.prepare_rgb_tx(
+            &rgb_info,
+            scriptpubkey,
+            amount_sat,
+            fee_rate,
+            min_conf,
+            sign_psbt,
+        )?;
         // FIXME: avoid cloning
         let txid = psbt.clone().extract_tx().txid();
         // Step 3: Make the cosignemtn and post it somewhere
@@ -125,36 +772,147 @@ impl RGBManager {
             txid.to_string(),
             Some(0),
         )?;
+        // The fundee learns from it how many assets we pushed
+        let funding_info = RgbFundingInfo {
+            contract_id: rgb_info.contract_id,
+            push_rgb_amount: rgb_info.remote_rgb_amount,
+        };
+        let funding_info_path = consignment_path.with_file_name("funding_info.json");
+        fs::write(&funding_info_path, json::to_string(&funding_info)?)?;
+        self.consignment_proxy().post_media(
+            &funding_info_path,
+            &funding_info_attachment_id(&txid.to_string()),
+        )?;
+        // The peer ACK is checked by the refresh task
+        self.storage
+            .update_rgb_info(&rgb_info.channel_id, true, &mut |info| {
+                info.funding_txid = Some(txid.to_string());
+                Ok(())
+            })?;
         return Ok(psbt);
     }
 
-    // Missing parameters: amount_sat of the funding tx and
-    // the script one
-    //
-    // Maybe it is possible extract from the tx if we know the information from
-    // the tx, but not sure if this is a good usage of cln hooks?
-    fn prepare_rgb_tx(
+    /// Broadcast the funding transaction, this must happen only after
+    /// cln secured the commitments with `fundchannel_complete`.
+    pub fn broadcast_rgb_funding_transaction(
+        &self,
+        psbt: &bitcoin::psbt::PartiallySignedTransaction,
+    ) -> anyhow::Result<String> {
+        let result = self.wallet.rgb_funding_end(psbt)?;
+        log::info!("rgb funding transaction `{}` broadcast", result.txid);
+        Ok(result.txid)
+    }
+
+    /// Pay the channel capacity `amount_sat` to the funding script, the
+    /// funding output carries the RGB amounts of both sides of the channel.
+    fn prepare_rgb_tx<F>(
         &self,
         info: &types::RgbInfo,
         scriptpubkey: bitcoin::ScriptBuf,
+        amount_sat: u64,
         fee_rate: f32,
         min_conf: u8,
-    ) -> anyhow::Result<bitcoin::psbt::PartiallySignedTransaction> {
+        sign_psbt: F,
+    ) -> anyhow::Result<bitcoin::psbt::PartiallySignedTransaction>
+    where
+        F: FnOnce(&mut bitcoin::psbt::PartiallySignedTransaction) -> anyhow::Result<()>,
+    {
         let recipient_map = amplify::map! {
             info.contract_id.to_string() => vec![Recipient {
                 recipient_data: RecipientData::WitnessData {
                     script_buf: scriptpubkey,
-                    amount_sat: info.remote_rgb_amount,
+                    amount_sat,
                     blinding: Some(STATIC_BLINDING),
                 },
-                amount: info.local_rgb_amount,
+                amount: info.local_rgb_amount + info.remote_rgb_amount,
                 transport_endpoints: vec![self.consignment_proxy.url.clone()]
             }]
         };
 
-        let psbt = self
-            .wallet
-            .rgb_funding_complete(recipient_map, fee_rate, min_conf)?;
+        let psbt =
+            self.wallet
+                .rgb_funding_complete(recipient_map, fee_rate, min_conf, sign_psbt)?;
         Ok(psbt)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rgb_lib::wallet::Balance;
+
+    use super::*;
+
+    fn contract_id(byte: u8) -> String {
+        ContractId::from([byte; 32]).to_string()
+    }
+
+    fn asset(byte: u8, ticker: &str, name: &str) -> types::RgbAssetInfo {
+        types::RgbAssetInfo {
+            asset_id: contract_id(byte),
+            schema: "NIA".to_owned(),
+            ticker: Some(ticker.to_owned()),
+            name: name.to_owned(),
+            precision: 0,
+            issued_supply: 1000,
+            balance: Balance {
+                settled: 1000,
+                future: 1000,
+                spendable: 1000,
+            },
+            channels_local_amount: 0,
+            pending_channels_local_amount: 0,
+        }
+    }
+
+    fn assets() -> Vec<types::RgbAssetInfo> {
+        vec![
+            asset(1, "USDT", "Tether"),
+            asset(2, "USDC", "USD Coin"),
+            asset(3, "USDT", "Fake Tether"),
+        ]
+    }
+
+    #[test]
+    fn resolve_contract_id() {
+        let id = contract_id(9);
+        let resolved = resolve_asset(&id, &HashMap::new(), &assets()).unwrap();
+        assert_eq!(resolved, id);
+    }
+
+    #[test]
+    fn resolve_name_and_ticker() {
+        let aliases = HashMap::new();
+        let resolved = resolve_asset("USDC", &aliases, &assets()).unwrap();
+        assert_eq!(resolved, contract_id(2));
+        let resolved = resolve_asset(" usd coin ", &aliases, &assets()).unwrap();
+        assert_eq!(resolved, contract_id(2));
+        let resolved = resolve_asset("tether/usdt", &aliases, &assets()).unwrap();
+        assert_eq!(resolved, contract_id(1));
+        assert!(resolve_asset("DAI", &aliases, &assets()).is_err());
+    }
+
+    #[test]
+    fn resolve_alias() {
+        let aliases = HashMap::from([("Dollar".to_owned(), contract_id(2))]);
+        let resolved = resolve_asset("dollar", &aliases, &assets()).unwrap();
+        assert_eq!(resolved, contract_id(2));
+        // an alias and a ticker of the same asset are not ambiguous
+        let aliases = HashMap::from([("usdc".to_owned(), contract_id(2))]);
+        let resolved = resolve_asset("USDC", &aliases, &assets()).unwrap();
+        assert_eq!(resolved, contract_id(2));
+
+        let aliases = HashMap::from([("dollar".to_owned(), "not a contract".to_owned())]);
+        assert!(resolve_asset("dollar", &aliases, &assets()).is_err());
+    }
+
+    #[test]
+    fn resolve_ambiguous() {
+        let aliases = HashMap::new();
+        let err = resolve_asset("USDT", &aliases, &assets()).unwrap_err();
+        assert!(err.to_string().contains("ambiguous"), "{err}");
+        // an alias does not shadow the ticker of another asset
+        let aliases = HashMap::from([("usdc".to_owned(), contract_id(1))]);
+        let err = resolve_asset("USDC", &aliases, &assets()).unwrap_err();
+        assert!(err.to_string().contains("ambiguous"), "{err}");
+    }
+}
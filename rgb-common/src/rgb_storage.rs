@@ -1,85 +1,707 @@
 //! RGB Storage interface
-use std::{cell::RefCell, collections::HashMap};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use crate::types::RgbInfo;
+use crate::types::{RgbForwardInfo, RgbHtlcInfo, RgbInfo, RgbPaymentInfo};
 
 /// A common interface for an RGB Storage
-pub trait RGBStorage {
-    fn new() -> anyhow::Result<Self>
-    where
-        Self: Sized;
+///
+/// The storage is shared between the RPC handlers and the
+/// background tasks, so every implementation must be safe to
+/// use concurrently.
+pub trait RGBStorage: Send + Sync {
+    /// Return the value stored under `key`, if any.
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>>;
 
-    fn get_rgb_channel_info(&self, channel_id: &str) -> anyhow::Result<RgbInfo>;
+    /// Store the `value` under `key`, replacing the previous one.
+    fn put(&self, key: &str, value: &str) -> anyhow::Result<()>;
 
-    fn get_rgb_channel_info_pending(&self, channel_id: &str) -> anyhow::Result<RgbInfo>;
+    /// Remove the value stored under `key`, if any.
+    fn delete(&self, key: &str) -> anyhow::Result<()>;
 
-    fn is_channel_rgb(&self, channel_id: &str, is_pending: bool) -> anyhow::Result<bool>;
+    /// Return the keys and the values stored directly under `prefix`, e.g.
+    /// the prefix `rgb/channel` returns every confirmed channel.
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<(String, String)>>;
+
+    /// Move the value stored under `from` to `to`, replacing the value
+    /// stored under `to` if any.
+    ///
+    /// When the backend is not able to do it atomically the value is
+    /// written under `to` before removing `from`, so a crash may leave
+    /// a copy under both keys but it never loses the value.
+    fn rename(&self, from: &str, to: &str) -> anyhow::Result<()>;
+
+    /// Atomic read-modify-write of the value stored under `key`.
+    ///
+    /// The `update` callback receives the current value and returns the
+    /// new one, returning `None` removes the key. If the value is modified
+    /// between the read and the write, the update is retried with the new
+    /// value, so the callback may be called more than once. The storage
+    /// is not locked while the callback runs, so it can use the storage.
+    fn transaction(
+        &self,
+        key: &str,
+        update: &mut dyn FnMut(Option<String>) -> anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()>;
+
+    fn get_rgb_channel_info(&self, channel_id: &str) -> anyhow::Result<RgbInfo> {
+        read_rgb_info(self, channel_id, false)
+    }
+
+    fn get_rgb_channel_info_pending(&self, channel_id: &str) -> anyhow::Result<RgbInfo> {
+        read_rgb_info(self, channel_id, true)
+    }
+
+    fn is_channel_rgb(&self, channel_id: &str, is_pending: bool) -> anyhow::Result<bool> {
+        let key = derive_channel_db_key(channel_id, is_pending)?;
+        Ok(self.get(&key)?.is_some())
+    }
 
     fn write_rgb_info(
         &self,
         channel_id: &str,
         is_pending: bool,
         info: &RgbInfo,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<()> {
+        let key = derive_channel_db_key(channel_id, is_pending)?;
+        self.put(&key, &serde_json::to_string(info)?)
+    }
+
+    fn remove_rgb_info(&self, channel_id: &str, is_pending: bool) -> anyhow::Result<()> {
+        let key = derive_channel_db_key(channel_id, is_pending)?;
+        self.delete(&key)
+    }
+
+    /// Return the info of every pending or confirmed channel,
+    /// the confirmed ones include the closed channels.
+    fn list_rgb_channel_info(&self, is_pending: bool) -> anyhow::Result<Vec<RgbInfo>> {
+        let prefix = if is_pending {
+            "rgb/pending/channel"
+        } else {
+            "rgb/channel"
+        };
+        self.list(prefix)?
+            .iter()
+            .map(|(_, value)| Ok(serde_json::from_str(value)?))
+            .collect()
+    }
+
+    /// Modify the channel info inside a transaction, this is
+    /// what should be used to update the channel balances.
+    fn update_rgb_info(
+        &self,
+        channel_id: &str,
+        is_pending: bool,
+        update: &mut dyn FnMut(&mut RgbInfo) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let key = derive_channel_db_key(channel_id, is_pending)?;
+        self.transaction(&key, &mut |value| {
+            let value =
+                value.ok_or(anyhow::anyhow!("rgb channel with key `{key}` is not found"))?;
+            let mut info: RgbInfo = serde_json::from_str(&value)?;
+            update(&mut info)?;
+            Ok(Some(serde_json::to_string(&info)?))
+        })
+    }
+
+    /// Move the RGB amount of the settled HTLCs inside the channel,
+    /// `rgb_offered_htlc` goes to the peer and `rgb_received_htlc` to us.
+    fn update_rgb_channel_amount(
+        &self,
+        channel_id: &str,
+        rgb_offered_htlc: u64,
+        rgb_received_htlc: u64,
+    ) -> anyhow::Result<()> {
+        self.update_rgb_info(channel_id, false, &mut |rgb_info| {
+            if rgb_offered_htlc > rgb_received_htlc {
+                let spent = rgb_offered_htlc - rgb_received_htlc;
+                rgb_info.local_rgb_amount =
+                    rgb_info
+                        .local_rgb_amount
+                        .checked_sub(spent)
+                        .ok_or(anyhow::anyhow!(
+                            "channel `{channel_id}` has not enough local rgb amount"
+                        ))?;
+                rgb_info.remote_rgb_amount += spent;
+            } else {
+                let received = rgb_received_htlc - rgb_offered_htlc;
+                rgb_info.remote_rgb_amount =
+                    rgb_info
+                        .remote_rgb_amount
+                        .checked_sub(received)
+                        .ok_or(anyhow::anyhow!(
+                            "channel `{channel_id}` has not enough remote rgb amount"
+                        ))?;
+                rgb_info.local_rgb_amount += received;
+            }
+            Ok(())
+        })
+    }
+
+    /// Return the channel id of the RGB channel with the `short_channel_id`.
+    fn get_rgb_channel_id(&self, short_channel_id: &str) -> anyhow::Result<Option<String>> {
+        self.get(&format!("rgb/scid/{short_channel_id}"))
+    }
+
+    fn write_rgb_channel_id(&self, short_channel_id: &str, channel_id: &str) -> anyhow::Result<()> {
+        self.put(&format!("rgb/scid/{short_channel_id}"), channel_id)
+    }
+
+    fn get_rgb_payment_info(&self, payment_hash: &str) -> anyhow::Result<RgbPaymentInfo> {
+        let key = derive_payment_db_key(payment_hash)?;
+        let value = self
+            .get(&key)?
+            .ok_or(anyhow::anyhow!("rgb payment with key `{key}` is not found"))?;
+        let info: RgbPaymentInfo = serde_json::from_str(&value)?;
+        Ok(info)
+    }
+
+    fn is_payment_rgb(&self, payment_hash: &str) -> anyhow::Result<bool> {
+        let key = derive_payment_db_key(payment_hash)?;
+        Ok(self.get(&key)?.is_some())
+    }
+
+    fn write_rgb_payment_info(
+        &self,
+        payment_hash: &str,
+        info: &RgbPaymentInfo,
+    ) -> anyhow::Result<()> {
+        let key = derive_payment_db_key(payment_hash)?;
+        self.put(&key, &serde_json::to_string(info)?)
+    }
+
+    fn write_rgb_htlc_info(&self, payment_hash: &str, info: &RgbHtlcInfo) -> anyhow::Result<()> {
+        let key = format!("rgb/htlc/{payment_hash}");
+        self.put(&key, &serde_json::to_string(info)?)
+    }
+
+    /// Remove the HTLC info and return it, if any.
+    ///
+    /// This happens inside a transaction so the same
+    /// HTLC can not be settled twice.
+    fn take_rgb_htlc_info(&self, payment_hash: &str) -> anyhow::Result<Option<RgbHtlcInfo>> {
+        let key = format!("rgb/htlc/{payment_hash}");
+        let mut taken = None;
+        self.transaction(&key, &mut |value| {
+            taken = value;
+            Ok(None)
+        })?;
+        let Some(value) = taken else {
+            return Ok(None);
+        };
+        let info: RgbHtlcInfo = serde_json::from_str(&value)?;
+        Ok(Some(info))
+    }
+
+    fn write_rgb_forward_info(
+        &self,
+        payment_hash: &str,
+        info: &RgbForwardInfo,
+    ) -> anyhow::Result<()> {
+        let key = format!("rgb/forward/{payment_hash}");
+        self.put(&key, &serde_json::to_string(info)?)
+    }
+
+    /// Remove the forward info and return it, if any.
+    fn take_rgb_forward_info(&self, payment_hash: &str) -> anyhow::Result<Option<RgbForwardInfo>> {
+        let key = format!("rgb/forward/{payment_hash}");
+        let mut taken = None;
+        self.transaction(&key, &mut |value| {
+            taken = value;
+            Ok(None)
+        })?;
+        let Some(value) = taken else {
+            return Ok(None);
+        };
+        let info: RgbForwardInfo = serde_json::from_str(&value)?;
+        Ok(Some(info))
+    }
+}
+
+/// Build the key where the channel info is stored, pending
+/// channels live in a different namespace from the confirmed one.
+pub fn derive_channel_db_key(channel_id: &str, is_pending: bool) -> anyhow::Result<String> {
+    return if is_pending {
+        Ok(format!("rgb/pending/channel/{channel_id}"))
+    } else {
+        Ok(format!("rgb/channel/{channel_id}"))
+    };
+}
+
+/// Build the key where the payment info is stored.
+pub fn derive_payment_db_key(payment_hash: &str) -> anyhow::Result<String> {
+    Ok(format!("rgb/payment/{payment_hash}"))
+}
+
+fn read_rgb_info<S: RGBStorage + ?Sized>(
+    storage: &S,
+    channel_id: &str,
+    is_pending: bool,
+) -> anyhow::Result<RgbInfo> {
+    let key = derive_channel_db_key(channel_id, is_pending)?;
+    let value = storage
+        .get(&key)?
+        .ok_or(anyhow::anyhow!("rgb channel with key `{key}` is not found"))?;
+    let info: RgbInfo = serde_json::from_str(&value)?;
+    Ok(info)
 }
 
 pub struct InMemoryStorage {
-    inner: RefCell<HashMap<String, String>>,
+    inner: Mutex<HashMap<String, String>>,
 }
 
 impl InMemoryStorage {
-    fn derive_channel_db_key(&self, channel_id: &str, is_pending: bool) -> anyhow::Result<String> {
-        return if is_pending {
-            Ok(format!("rgb/pending/channel/{channel_id}"))
-        } else {
-            Ok(format!("rgb/channel/{channel_id}"))
-        };
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            inner: Mutex::new(HashMap::new()),
+        })
     }
 }
 
 impl RGBStorage for InMemoryStorage {
-    fn new() -> anyhow::Result<Self> {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let map = self.inner.lock().unwrap();
+        Ok(map.get(key).cloned())
+    }
+
+    fn put(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let mut map = self.inner.lock().unwrap();
+        map.insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let mut map = self.inner.lock().unwrap();
+        map.remove(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let map = self.inner.lock().unwrap();
+        let prefix = format!("{prefix}/");
+        let values = map
+            .iter()
+            .filter(|(key, _)| {
+                key.strip_prefix(&prefix)
+                    .map_or(false, |name| !name.contains('/'))
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Ok(values)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let mut map = self.inner.lock().unwrap();
+        let value = map
+            .remove(from)
+            .ok_or(anyhow::anyhow!("storage key `{from}` is not found"))?;
+        map.insert(to.to_owned(), value);
+        Ok(())
+    }
+
+    fn transaction(
+        &self,
+        key: &str,
+        update: &mut dyn FnMut(Option<String>) -> anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()> {
+        loop {
+            let current = self.get(key)?;
+            let value = update(current.clone())?;
+            let mut map = self.inner.lock().unwrap();
+            if map.get(key) != current.as_ref() {
+                continue;
+            }
+            match value {
+                Some(value) => map.insert(key.to_owned(), value),
+                None => map.remove(key),
+            };
+            return Ok(());
+        }
+    }
+}
+
+/// Storage that keeps each record in its own file inside the
+/// plugin root directory, so the information survives a restart
+/// of lightningd.
+///
+/// The database key is used as relative path, e.g. the key
+/// `rgb/pending/channel/<id>` is stored in `<root>/rgb/pending/channel/<id>`.
+pub struct FileStorage {
+    root: PathBuf,
+    /// Serialize the writes, so the check of a transaction
+    /// is never interleaved with another write.
+    lock: Mutex<()>,
+}
+
+impl FileStorage {
+    pub fn new(root_dir: &str) -> anyhow::Result<Self> {
+        let root = Path::new(root_dir).to_path_buf();
+        fs::create_dir_all(root.join("rgb"))?;
         Ok(Self {
-            inner: RefCell::new(HashMap::new()),
+            root,
+            lock: Mutex::new(()),
         })
     }
 
-    fn get_rgb_channel_info(&self, channel_id: &str) -> anyhow::Result<RgbInfo> {
-        let key = self.derive_channel_db_key(channel_id, false)?;
-        let map = self.inner.borrow();
-        let value = map
-            .get(&key)
-            .ok_or(anyhow::anyhow!("rgb channel with key `{key}` is not found"))?;
-        let info: RgbInfo = serde_json::from_str(&value)?;
-        Ok(info)
+    fn key_path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        if key.split('/').any(|part| part.is_empty() || part == "..") {
+            anyhow::bail!("invalid storage key `{key}`");
+        }
+        Ok(self.root.join(key))
     }
 
-    fn get_rgb_channel_info_pending(&self, channel_id: &str) -> anyhow::Result<RgbInfo> {
-        let key = self.derive_channel_db_key(channel_id, true)?;
-        let map = self.inner.borrow();
-        let value = map
-            .get(&key)
-            .ok_or(anyhow::anyhow!("rgb channel with key `{key}` is not found"))?;
-        let info: RgbInfo = serde_json::from_str(&value)?;
-        Ok(info)
+    fn read(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let path = self.key_path(key)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(path)?))
     }
 
-    fn is_channel_rgb(&self, channel_id: &str, is_pending: bool) -> anyhow::Result<bool> {
-        let key = self.derive_channel_db_key(channel_id, is_pending)?;
-        let map = self.inner.borrow();
-        Ok(map.contains_key(&key))
+    /// Write the value in a temporary file and then rename it, so
+    /// a crash never leaves a record half written.
+    fn write(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let path = self.key_path(key)?;
+        let parent = path
+            .parent()
+            .ok_or(anyhow::anyhow!("storage key `{key}` has no parent"))?;
+        fs::create_dir_all(parent)?;
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(value.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
     }
 
-    fn write_rgb_info(
+    fn remove(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.key_path(key)?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl RGBStorage for FileStorage {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let _guard = self.lock.lock().unwrap();
+        self.read(key)
+    }
+
+    fn put(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.write(key, value)
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.remove(key)
+    }
+
+    fn list(&self, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let _guard = self.lock.lock().unwrap();
+        let dir = self.key_path(prefix)?;
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut values = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            // skip the sub namespaces and the writes in progress
+            if !path.is_file() || path.extension().map_or(false, |ext| ext == "tmp") {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            values.push((format!("{prefix}/{name}"), fs::read_to_string(&path)?));
+        }
+        Ok(values)
+    }
+
+    /// The rename of a file is atomic, so the value is never
+    /// under both keys.
+    fn rename(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let from_path = self.key_path(from)?;
+        let to_path = self.key_path(to)?;
+        if !from_path.exists() {
+            anyhow::bail!("storage key `{from}` is not found");
+        }
+        let parent = to_path
+            .parent()
+            .ok_or(anyhow::anyhow!("storage key `{to}` has no parent"))?;
+        fs::create_dir_all(parent)?;
+        fs::rename(from_path, to_path)?;
+        Ok(())
+    }
+
+    fn transaction(
         &self,
-        channel_id: &str,
-        is_pending: bool,
-        info: &RgbInfo,
+        key: &str,
+        update: &mut dyn FnMut(Option<String>) -> anyhow::Result<Option<String>>,
     ) -> anyhow::Result<()> {
-        let key = self.derive_channel_db_key(channel_id, is_pending)?;
-        // FIXME: we need a lock before production
-        let mut map = self.inner.borrow_mut();
-        map.insert(key, serde_json::to_string(info)?);
-        Ok(())
+        loop {
+            let current = self.get(key)?;
+            let value = update(current.clone())?;
+            let _guard = self.lock.lock().unwrap();
+            if self.read(key)? != current {
+                continue;
+            }
+            return match value {
+                Some(value) => self.write(key, &value),
+                None => self.remove(key),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::std::contract::ContractId;
+
+    fn file_storage(name: &str) -> FileStorage {
+        let dir = std::env::temp_dir().join(format!("rgb-storage-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        FileStorage::new(dir.to_str().unwrap()).unwrap()
+    }
+
+    fn storages(name: &str) -> Vec<Arc<dyn RGBStorage>> {
+        vec![
+            Arc::new(InMemoryStorage::new().unwrap()),
+            Arc::new(file_storage(name)),
+        ]
+    }
+
+    #[test]
+    fn get_put_delete() {
+        for storage in storages("get-put-delete") {
+            assert_eq!(storage.get("rgb/channel/a").unwrap(), None);
+            storage.put("rgb/channel/a", "first").unwrap();
+            storage.put("rgb/channel/a", "second").unwrap();
+            assert_eq!(
+                storage.get("rgb/channel/a").unwrap().as_deref(),
+                Some("second")
+            );
+            storage.delete("rgb/channel/a").unwrap();
+            assert_eq!(storage.get("rgb/channel/a").unwrap(), None);
+            // deleting a missing key is not an error
+            storage.delete("rgb/channel/a").unwrap();
+        }
+    }
+
+    #[test]
+    fn list_returns_direct_children() {
+        for storage in storages("list") {
+            assert!(storage.list("rgb/channel").unwrap().is_empty());
+            storage.put("rgb/channel/a", "a").unwrap();
+            storage.put("rgb/channel/b", "b").unwrap();
+            storage.put("rgb/pending/channel/c", "c").unwrap();
+            storage.put("rgb/channel/nested/d", "d").unwrap();
+
+            let mut values = storage.list("rgb/channel").unwrap();
+            values.sort();
+            assert_eq!(
+                values,
+                vec![
+                    ("rgb/channel/a".to_owned(), "a".to_owned()),
+                    ("rgb/channel/b".to_owned(), "b".to_owned()),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn rename_moves_the_value() {
+        for storage in storages("rename") {
+            assert!(storage
+                .rename("rgb/pending/channel/a", "rgb/channel/a")
+                .is_err());
+            storage.put("rgb/pending/channel/a", "a").unwrap();
+            storage.put("rgb/channel/a", "old").unwrap();
+            storage
+                .rename("rgb/pending/channel/a", "rgb/channel/a")
+                .unwrap();
+            assert_eq!(storage.get("rgb/pending/channel/a").unwrap(), None);
+            assert_eq!(storage.get("rgb/channel/a").unwrap().as_deref(), Some("a"));
+        }
+    }
+
+    #[test]
+    fn transaction_updates_and_removes() {
+        for storage in storages("transaction") {
+            storage
+                .transaction("rgb/htlc/a", &mut |value| {
+                    assert_eq!(value, None);
+                    Ok(Some("1".to_owned()))
+                })
+                .unwrap();
+            assert_eq!(storage.get("rgb/htlc/a").unwrap().as_deref(), Some("1"));
+
+            storage
+                .transaction("rgb/htlc/a", &mut |value| {
+                    assert_eq!(value.as_deref(), Some("1"));
+                    Ok(None)
+                })
+                .unwrap();
+            assert_eq!(storage.get("rgb/htlc/a").unwrap(), None);
+
+            // a failing callback leaves the value untouched
+            storage.put("rgb/htlc/a", "1").unwrap();
+            let result =
+                storage.transaction("rgb/htlc/a", &mut |_| anyhow::bail!("callback failure"));
+            assert!(result.is_err());
+            assert_eq!(storage.get("rgb/htlc/a").unwrap().as_deref(), Some("1"));
+        }
+    }
+
+    #[test]
+    fn transaction_callback_can_use_the_storage() {
+        for storage in storages("transaction-reentrant") {
+            storage.put("rgb/channel/a", "a").unwrap();
+            let inner = storage.clone();
+            storage
+                .transaction("rgb/htlc/a", &mut |_| {
+                    let value = inner.get("rgb/channel/a")?;
+                    Ok(value)
+                })
+                .unwrap();
+            assert_eq!(storage.get("rgb/htlc/a").unwrap().as_deref(), Some("a"));
+        }
+    }
+
+    #[test]
+    fn transaction_retries_when_the_value_changes() {
+        for storage in storages("transaction-retry") {
+            storage.put("rgb/htlc/a", "0").unwrap();
+            let inner = storage.clone();
+            let mut calls = 0;
+            storage
+                .transaction("rgb/htlc/a", &mut |value| {
+                    calls += 1;
+                    // somebody else writes the key during the first call
+                    if calls == 1 {
+                        inner.put("rgb/htlc/a", "10")?;
+                    }
+                    let value: u64 = value.unwrap().parse()?;
+                    Ok(Some((value + 1).to_string()))
+                })
+                .unwrap();
+            assert_eq!(calls, 2);
+            assert_eq!(storage.get("rgb/htlc/a").unwrap().as_deref(), Some("11"));
+        }
+    }
+
+    #[test]
+    fn concurrent_transactions_are_not_lost() {
+        for storage in storages("transaction-concurrent") {
+            storage.put("rgb/htlc/a", "0").unwrap();
+            let workers = (0..8)
+                .map(|_| {
+                    let storage = storage.clone();
+                    thread::spawn(move || {
+                        for _ in 0..25 {
+                            storage
+                                .transaction("rgb/htlc/a", &mut |value| {
+                                    let value: u64 = value.unwrap().parse()?;
+                                    Ok(Some((value + 1).to_string()))
+                                })
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            assert_eq!(storage.get("rgb/htlc/a").unwrap().as_deref(), Some("200"));
+        }
+    }
+
+    #[test]
+    fn update_rgb_channel_amount_moves_the_balance() {
+        for storage in storages("channel-amount") {
+            let info = RgbInfo {
+                channel_id: "a".to_owned(),
+                short_channel_id: None,
+                contract_id: ContractId::from([1u8; 32]),
+                local_rgb_amount: 100,
+                remote_rgb_amount: 0,
+                state: Default::default(),
+                funding_txid: None,
+                funding_ack: None,
+            };
+            storage.write_rgb_info("a", false, &info).unwrap();
+
+            storage.update_rgb_channel_amount("a", 30, 0).unwrap();
+            let info = storage.get_rgb_channel_info("a").unwrap();
+            assert_eq!((info.local_rgb_amount, info.remote_rgb_amount), (70, 30));
+            storage.update_rgb_channel_amount("a", 0, 10).unwrap();
+            let info = storage.get_rgb_channel_info("a").unwrap();
+            assert_eq!((info.local_rgb_amount, info.remote_rgb_amount), (80, 20));
+
+            // the balance never goes below zero
+            assert!(storage.update_rgb_channel_amount("a", 81, 0).is_err());
+            assert!(storage.update_rgb_channel_amount("a", 0, 21).is_err());
+            let info = storage.get_rgb_channel_info("a").unwrap();
+            assert_eq!((info.local_rgb_amount, info.remote_rgb_amount), (80, 20));
+        }
+    }
+
+    #[test]
+    fn take_rgb_forward_info_only_once() {
+        for storage in storages("forward-info") {
+            let info = RgbForwardInfo {
+                in_channel_id: "a".to_owned(),
+                out_channel_id: "b".to_owned(),
+                contract_id: ContractId::from([1u8; 32]),
+                amount: 10,
+            };
+            storage.write_rgb_forward_info("hash", &info).unwrap();
+            let taken = storage.take_rgb_forward_info("hash").unwrap().unwrap();
+            assert_eq!((taken.in_channel_id.as_str(), taken.amount), ("a", 10));
+            assert!(storage.take_rgb_forward_info("hash").unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn file_storage_refuses_invalid_keys() {
+        let storage = file_storage("invalid-keys");
+        for key in [
+            "rgb/../secret",
+            "rgb//channel",
+            "/rgb/channel",
+            "rgb/channel/",
+        ] {
+            assert!(storage.put(key, "value").is_err(), "{key}");
+            assert!(storage.get(key).is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn file_storage_does_not_leave_temporary_files() {
+        let storage = file_storage("temporary-files");
+        storage.put("rgb/channel/a", "first").unwrap();
+        storage.put("rgb/channel/a", "second").unwrap();
+        storage
+            .transaction("rgb/channel/b", &mut |_| Ok(Some("b".to_owned())))
+            .unwrap();
+        let files = fs::read_dir(storage.root.join("rgb/channel"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 2, "{files:?}");
+        assert!(
+            files.iter().all(|name| !name.ends_with(".tmp")),
+            "{files:?}"
+        );
     }
 }
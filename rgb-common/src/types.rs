@@ -1,20 +1,72 @@
 //! RGB types
 use std::collections::BTreeMap;
+use std::str::FromStr;
 
 use commit_verify::mpc::MerkleBlock;
+use rgb_lib::wallet::Balance;
 use serde::{Deserialize, Serialize};
 
 use crate::bitcoin::Txid;
 use crate::core::{Anchor, TransitionBundle};
 use crate::std::contract::ContractId;
 
-/// RGB Send asset data
+/// RGB Send asset data, a single transaction can
+/// pay many recipients of different assets.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RGBSendAssetData {
+    pub recipients: Vec<RGBSendRecipient>,
+    pub donation: bool,
+}
+
+/// RGB Send recipient
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RGBSendRecipient {
     pub asset_id: String,
     pub amount: u64,
+    /// The blinded UTXO, or the script of the receiver
+    /// output when the receiver asked for a witness receive.
+    #[serde(alias = "recipient_id")]
     pub blinded_utxo: String,
-    pub donation: bool,
+    /// Bitcoin amount of the output created for a witness
+    /// receive, `None` for a blinded receive.
+    #[serde(default)]
+    pub witness_amount_sat: Option<u64>,
+    /// Where the consignment is posted, our proxy when empty.
+    #[serde(default)]
+    pub transport_endpoints: Vec<String>,
+}
+
+/// RGB asset known by the wallet
+#[derive(Debug, Clone, Serialize)]
+pub struct RgbAssetInfo {
+    pub asset_id: String,
+    /// `NIA`, `CFA` or `UDA`
+    pub schema: String,
+    pub ticker: Option<String>,
+    pub name: String,
+    pub precision: u8,
+    pub issued_supply: u64,
+    pub balance: Balance,
+    /// Amount locked on our side of the open RGB channels.
+    pub channels_local_amount: u64,
+    /// Amount locked on our side of the RGB channels whose
+    /// funding transaction is not confirmed yet.
+    pub pending_channels_local_amount: u64,
+}
+
+/// RGB channel state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RgbChannelState {
+    /// The funding transaction is not confirmed yet
+    #[default]
+    Opening,
+    /// The channel is ready to be used
+    Open,
+    /// The channel is closing, the RGB amounts are the final allocations
+    Closing,
+    /// The channel is closed, the RGB amounts are the final allocations
+    Closed,
 }
 
 /// RGB channel info
@@ -22,12 +74,35 @@ pub struct RGBSendAssetData {
 pub struct RgbInfo {
     /// Channel_id
     pub channel_id: String,
+    /// Channel short channel id, known only when the channel is confirmed
+    #[serde(default)]
+    pub short_channel_id: Option<String>,
     /// Channel contract ID
     pub contract_id: ContractId,
     /// Channel RGB local amount
     pub local_rgb_amount: u64,
     /// Channel RGB remote amount
     pub remote_rgb_amount: u64,
+    /// Channel state
+    #[serde(default)]
+    pub state: RgbChannelState,
+    /// Funding transaction id, the funding consignment is posted under it
+    #[serde(default)]
+    pub funding_txid: Option<String>,
+    /// Peer answer to the funding consignment, `None` until it answers
+    #[serde(default)]
+    pub funding_ack: Option<bool>,
+}
+
+/// How the funder splits the RGB amount of a new channel, posted
+/// as a media next to the funding consignment so the fundee knows
+/// its side of the channel.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RgbFundingInfo {
+    /// Channel contract ID
+    pub contract_id: ContractId,
+    /// RGB amount pushed to the fundee
+    pub push_rgb_amount: u64,
 }
 
 /// RGB payment info
@@ -45,6 +120,93 @@ pub struct RgbPaymentInfo {
     pub override_route_amount: bool,
 }
 
+/// RGB information that a Lightning invoice carries inside its
+/// description, so the payer knows which asset should be routed.
+///
+/// The information are appended to the description as a
+/// `rgb_contract_id=<contract id> rgb_amount=<amount>` line.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RgbInvoiceInfo {
+    /// RGB contract ID
+    pub contract_id: ContractId,
+    /// RGB amount to pay
+    pub amount: u64,
+}
+
+impl RgbInvoiceInfo {
+    /// Append the RGB information to the invoice `description`.
+    pub fn encode_description(&self, description: &str) -> String {
+        format!(
+            "{description}\nrgb_contract_id={} rgb_amount={}",
+            self.contract_id, self.amount
+        )
+    }
+
+    /// Read the RGB information from the invoice `description`, if any.
+    pub fn decode_description(description: &str) -> anyhow::Result<Option<Self>> {
+        let Some(line) = description.lines().last() else {
+            return Ok(None);
+        };
+        let mut contract_id = None;
+        let mut amount = None;
+        for token in line.split_whitespace() {
+            match token.split_once('=') {
+                Some(("rgb_contract_id", value)) => {
+                    contract_id = Some(
+                        ContractId::from_str(value)
+                            .map_err(|err| anyhow::anyhow!("invalid rgb contract id: {err}"))?,
+                    )
+                }
+                Some(("rgb_amount", value)) => amount = Some(value.parse::<u64>()?),
+                _ => continue,
+            }
+        }
+        let (Some(contract_id), Some(amount)) = (contract_id, amount) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            contract_id,
+            amount,
+        }))
+    }
+}
+
+/// RGB HTLC direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RgbHtlcDirection {
+    /// The HTLC is offered by us
+    Offered,
+    /// The HTLC is received by us
+    Received,
+}
+
+/// RGB HTLC info, kept until the HTLC is settled or failed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RgbHtlcInfo {
+    /// Channel where the HTLC is
+    pub channel_id: String,
+    /// RGB contract ID
+    pub contract_id: ContractId,
+    /// RGB amount carried by the HTLC
+    pub amount: u64,
+    /// HTLC direction
+    pub direction: RgbHtlcDirection,
+}
+
+/// RGB forward info, kept until the forwarded HTLC is settled or failed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RgbForwardInfo {
+    /// Channel where the HTLC is received
+    pub in_channel_id: String,
+    /// Channel where the HTLC is offered
+    pub out_channel_id: String,
+    /// RGB contract ID
+    pub contract_id: ContractId,
+    /// RGB amount carried by the HTLC
+    pub amount: u64,
+}
+
 /// RGB transfer info
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TransferInfo {
@@ -69,3 +231,57 @@ pub struct OutPoint {
     /// The index of the referenced output in its transaction's vout.
     pub index: u16,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invoice_info() -> RgbInvoiceInfo {
+        RgbInvoiceInfo {
+            contract_id: ContractId::from([1u8; 32]),
+            amount: 10,
+        }
+    }
+
+    #[test]
+    fn invoice_info_round_trip() {
+        let info = invoice_info();
+        let description = info.encode_description("coffee");
+        assert!(description.starts_with("coffee\n"), "{description}");
+        let decoded = RgbInvoiceInfo::decode_description(&description).unwrap();
+        assert_eq!(decoded, Some(info.clone()));
+
+        // a multiline description keeps the rgb line at the end
+        let description = info.encode_description("coffee\nand cake");
+        let decoded = RgbInvoiceInfo::decode_description(&description).unwrap();
+        assert_eq!(decoded, Some(info));
+    }
+
+    #[test]
+    fn invoice_info_missing() {
+        assert_eq!(RgbInvoiceInfo::decode_description("").unwrap(), None);
+        assert_eq!(RgbInvoiceInfo::decode_description("coffee").unwrap(), None);
+        let contract_id = ContractId::from([1u8; 32]);
+        let only_contract = format!("coffee\nrgb_contract_id={contract_id}");
+        assert_eq!(
+            RgbInvoiceInfo::decode_description(&only_contract).unwrap(),
+            None
+        );
+        let only_amount = "coffee\nrgb_amount=10";
+        assert_eq!(
+            RgbInvoiceInfo::decode_description(only_amount).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn invoice_info_malformed() {
+        let contract_id = ContractId::from([1u8; 32]);
+        let bad_contract = "coffee\nrgb_contract_id=not-a-contract rgb_amount=10";
+        assert!(RgbInvoiceInfo::decode_description(bad_contract).is_err());
+        let bad_amount = format!("coffee\nrgb_contract_id={contract_id} rgb_amount=ten");
+        assert!(RgbInvoiceInfo::decode_description(&bad_amount).is_err());
+        let negative_amount = format!("coffee\nrgb_contract_id={contract_id} rgb_amount=-1");
+        assert!(RgbInvoiceInfo::decode_description(&negative_amount).is_err());
+    }
+}
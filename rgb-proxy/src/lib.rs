@@ -0,0 +1,41 @@
+//! A small RGB proxy server
+//!
+//! Implement the RGB HTTP JSON-RPC protocol used by the RGB wallets to
+//! exchange the consignments, so the integration tests do not need an
+//! external proxy and an operator can run one next to the node.
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+pub mod server;
+pub mod storage;
+
+use storage::FileStore;
+
+/// Address where the plugin looks for the proxy on regtest.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:3000";
+
+/// Run the proxy in a background thread with its own runtime,
+/// and return when the server is listening or failed to start.
+pub fn spawn(addr: SocketAddr, data_dir: &Path) -> anyhow::Result<()> {
+    let store = FileStore::new(data_dir)?;
+    // We bind the socket here to report the error to the caller.
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                let _ = sender.send(Err(anyhow::anyhow!("{err}")));
+                return;
+            }
+        };
+        let _ = sender.send(Ok(()));
+        if let Err(err) = runtime.block_on(server::serve_with_listener(listener, store)) {
+            log::error!("rgb proxy stopped: {err}");
+        }
+    });
+    receiver.recv()?
+}
@@ -0,0 +1,37 @@
+//! RGB proxy server
+//!
+//! Usage: `rgb-proxy [--addr <host:port>] [--data-dir <path>]`
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use rgb_proxy::server;
+use rgb_proxy::storage::FileStore;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut addr = rgb_proxy::DEFAULT_ADDR.to_owned();
+    let mut data_dir = PathBuf::from(".rgb-proxy");
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => {
+                addr = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("`--addr` needs a value"))?
+            }
+            "--data-dir" => {
+                data_dir = args
+                    .next()
+                    .ok_or(anyhow::anyhow!("`--data-dir` needs a value"))?
+                    .into()
+            }
+            _ => anyhow::bail!("unknown argument `{arg}`"),
+        }
+    }
+
+    let addr: SocketAddr = addr.parse()?;
+    let store = FileStore::new(&data_dir)?;
+    server::serve(addr, store).await
+}
@@ -0,0 +1,566 @@
+//! HTTP JSON-RPC server
+//!
+//! The requests are accepted as `application/json` or as
+//! `multipart/form-data`, the latter is needed by the methods that
+//! upload a file (`consignment.post` and `media.post`).
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json as json;
+use serde_json::Value;
+
+use crate::storage::{AckUpdate, Consignment, FileStore};
+
+/// Protocol version implemented by the server.
+pub const PROTOCOL_VERSION: &str = "0.2";
+
+// JSON-RPC error codes, the codes from -32768 to -32000 are defined
+// by the JSON-RPC specification, the others are specific to the proxy.
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const CANNOT_CHANGE_ACK: i64 = -101;
+pub const CANNOT_CHANGE_UPLOADED_FILE: i64 = -102;
+pub const MISSING_FILE: i64 = -103;
+pub const NOT_FOUND_CONSIGNMENT: i64 = -104;
+pub const NOT_FOUND_MEDIA: i64 = -105;
+
+#[derive(Debug)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        log::error!("rgb proxy internal error: {err}");
+        Self::new(INTERNAL_ERROR, &format!("{err}"))
+    }
+}
+
+/// A JSON-RPC request with the file attached to it, if any.
+#[derive(Debug)]
+struct RpcRequest {
+    id: Value,
+    method: String,
+    params: Value,
+    file: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecipientIdParam {
+    recipient_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsignmentPostParam {
+    recipient_id: String,
+    txid: String,
+    vout: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AckPostParam {
+    recipient_id: String,
+    ack: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct AttachmentIdParam {
+    attachment_id: String,
+}
+
+struct ProxyState {
+    store: FileStore,
+    started_at: Instant,
+}
+
+fn params<T: DeserializeOwned>(request: &RpcRequest) -> Result<T, RpcError> {
+    json::from_value(request.params.clone())
+        .map_err(|err| RpcError::new(INVALID_PARAMS, &format!("{err}")))
+}
+
+fn file(request: &RpcRequest) -> Result<String, RpcError> {
+    let file = request
+        .file
+        .as_ref()
+        .ok_or(RpcError::new(MISSING_FILE, "file is missing"))?;
+    Ok(base64::encode(file))
+}
+
+fn dispatch(state: &ProxyState, request: &RpcRequest) -> Result<Value, RpcError> {
+    log::debug!("rgb proxy call `{}`", request.method);
+    match request.method.as_str() {
+        "server.info" => Ok(json::json!({
+            "protocol_version": PROTOCOL_VERSION,
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptime": state.started_at.elapsed().as_secs(),
+        })),
+        "consignment.get" => {
+            let param: RecipientIdParam = params(request)?;
+            let consignment =
+                state
+                    .store
+                    .get_consignment(&param.recipient_id)?
+                    .ok_or(RpcError::new(
+                        NOT_FOUND_CONSIGNMENT,
+                        "consignment not found",
+                    ))?;
+            Ok(json::json!({
+                "consignment": consignment.consignment,
+                "txid": consignment.txid,
+                "vout": consignment.vout,
+            }))
+        }
+        "consignment.post" => {
+            let param: ConsignmentPostParam = params(request)?;
+            let consignment = Consignment {
+                consignment: file(request)?,
+                txid: param.txid,
+                vout: param.vout,
+                ack: None,
+            };
+            match state
+                .store
+                .put_consignment(&param.recipient_id, &consignment)?
+            {
+                None => Ok(json::json!(true)),
+                // uploading the same consignment twice is not an error
+                Some(stored) if stored.consignment == consignment.consignment => {
+                    Ok(json::json!(false))
+                }
+                Some(_) => Err(RpcError::new(
+                    CANNOT_CHANGE_UPLOADED_FILE,
+                    "a different consignment is already uploaded",
+                )),
+            }
+        }
+        "ack.get" => {
+            let param: RecipientIdParam = params(request)?;
+            let consignment =
+                state
+                    .store
+                    .get_consignment(&param.recipient_id)?
+                    .ok_or(RpcError::new(
+                        NOT_FOUND_CONSIGNMENT,
+                        "consignment not found",
+                    ))?;
+            Ok(json::json!(consignment.ack))
+        }
+        "ack.post" => {
+            let param: AckPostParam = params(request)?;
+            match state.store.set_ack(&param.recipient_id, param.ack)? {
+                AckUpdate::Updated => Ok(json::json!(true)),
+                AckUpdate::AlreadySet(_) => {
+                    Err(RpcError::new(CANNOT_CHANGE_ACK, "ack is already set"))
+                }
+                AckUpdate::NotFound => Err(RpcError::new(
+                    NOT_FOUND_CONSIGNMENT,
+                    "consignment not found",
+                )),
+            }
+        }
+        "media.get" => {
+            let param: AttachmentIdParam = params(request)?;
+            let media = state
+                .store
+                .get_media(&param.attachment_id)?
+                .ok_or(RpcError::new(NOT_FOUND_MEDIA, "media not found"))?;
+            Ok(json::json!(media))
+        }
+        "media.post" => {
+            let param: AttachmentIdParam = params(request)?;
+            let media = file(request)?;
+            match state.store.put_media(&param.attachment_id, &media)? {
+                None => Ok(json::json!(true)),
+                Some(stored) if stored == media => Ok(json::json!(false)),
+                Some(_) => Err(RpcError::new(
+                    CANNOT_CHANGE_UPLOADED_FILE,
+                    "a different media is already uploaded",
+                )),
+            }
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, "method not found")),
+    }
+}
+
+async fn parse_multipart(body: Body, boundary: String) -> Result<RpcRequest, RpcError> {
+    let parse_err = |err: multer::Error| RpcError::new(PARSE_ERROR, &format!("{err}"));
+    let mut multipart = multer::Multipart::new(body, boundary);
+    let mut fields = HashMap::new();
+    let mut file = None;
+    while let Some(field) = multipart.next_field().await.map_err(parse_err)? {
+        match field.name().map(|name| name.to_owned()) {
+            Some(name) if name == "file" => {
+                file = Some(field.bytes().await.map_err(parse_err)?.to_vec());
+            }
+            Some(name) => {
+                fields.insert(name, field.text().await.map_err(parse_err)?);
+            }
+            None => continue,
+        }
+    }
+    let method = fields
+        .remove("method")
+        .ok_or(RpcError::new(PARSE_ERROR, "method is missing"))?;
+    let params = match fields.remove("params") {
+        Some(params) => {
+            json::from_str(&params).map_err(|err| RpcError::new(PARSE_ERROR, &format!("{err}")))?
+        }
+        None => Value::Null,
+    };
+    Ok(RpcRequest {
+        id: fields.remove("id").map(Value::String).unwrap_or_default(),
+        method,
+        params,
+        file,
+    })
+}
+
+async fn parse_request(request: Request<Body>) -> Result<RpcRequest, RpcError> {
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if let Ok(boundary) = multer::parse_boundary(&content_type) {
+        return parse_multipart(request.into_body(), boundary).await;
+    }
+    let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|err| RpcError::new(PARSE_ERROR, &format!("{err}")))?;
+    let request: JsonRequest =
+        json::from_slice(&body).map_err(|err| RpcError::new(PARSE_ERROR, &format!("{err}")))?;
+    Ok(RpcRequest {
+        id: request.id,
+        method: request.method,
+        params: request.params,
+        file: None,
+    })
+}
+
+async fn handle(
+    state: Arc<ProxyState>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::POST || request.uri().path() != "/json-rpc" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let (id, result) = match parse_request(request).await {
+        Ok(request) => {
+            // the storage is blocking, so we move it out of the async runtime
+            let state = state.clone();
+            let id = request.id.clone();
+            let result = tokio::task::spawn_blocking(move || dispatch(&state, &request))
+                .await
+                .unwrap_or_else(|err| Err(RpcError::new(INTERNAL_ERROR, &format!("{err}"))));
+            (id, result)
+        }
+        Err(err) => (Value::Null, Err(err)),
+    };
+    let body = match result {
+        Ok(result) => json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    };
+    let mut response = Response::new(Body::from(body.to_string()));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    Ok(response)
+}
+
+/// Run the proxy server on `addr` until the future is dropped.
+pub async fn serve(addr: SocketAddr, store: FileStore) -> anyhow::Result<()> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    serve_with_listener(listener, store).await
+}
+
+/// Run the proxy server on a socket that is already bound.
+pub async fn serve_with_listener(
+    listener: std::net::TcpListener,
+    store: FileStore,
+) -> anyhow::Result<()> {
+    let addr = listener.local_addr()?;
+    let state = Arc::new(ProxyState {
+        store,
+        started_at: Instant::now(),
+    });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(state.clone(), request))) }
+    });
+    let server = Server::from_tcp(listener)?.serve(make_service);
+    log::info!("rgb proxy listening on `http://{addr}/json-rpc`");
+    server.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::temp_store;
+
+    fn state(name: &str) -> ProxyState {
+        ProxyState {
+            store: temp_store(name),
+            started_at: Instant::now(),
+        }
+    }
+
+    fn call(
+        state: &ProxyState,
+        method: &str,
+        params: Value,
+        file: Option<&[u8]>,
+    ) -> Result<Value, i64> {
+        let request = RpcRequest {
+            id: json::json!("1"),
+            method: method.to_owned(),
+            params,
+            file: file.map(|file| file.to_vec()),
+        };
+        dispatch(state, &request).map_err(|err| err.code)
+    }
+
+    #[test]
+    fn server_info() {
+        let state = state("server-info");
+        let info = call(&state, "server.info", Value::Null, None).unwrap();
+        assert_eq!(info["protocol_version"], PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn unknown_method() {
+        let state = state("unknown-method");
+        assert_eq!(
+            call(&state, "consignment.delete", Value::Null, None),
+            Err(METHOD_NOT_FOUND)
+        );
+    }
+
+    #[test]
+    fn invalid_params() {
+        let state = state("invalid-params");
+        assert_eq!(
+            call(&state, "consignment.get", json::json!({}), None),
+            Err(INVALID_PARAMS)
+        );
+        assert_eq!(
+            call(
+                &state,
+                "ack.post",
+                json::json!({ "recipient_id": "id" }),
+                None
+            ),
+            Err(INVALID_PARAMS)
+        );
+    }
+
+    #[test]
+    fn consignment_post_and_get() {
+        let state = state("consignment");
+        let params = json::json!({ "recipient_id": "id", "txid": "txid", "vout": 1 });
+        assert_eq!(
+            call(
+                &state,
+                "consignment.get",
+                json::json!({ "recipient_id": "id" }),
+                None
+            ),
+            Err(NOT_FOUND_CONSIGNMENT)
+        );
+        assert_eq!(
+            call(&state, "consignment.post", params.clone(), None),
+            Err(MISSING_FILE)
+        );
+        assert_eq!(
+            call(&state, "consignment.post", params.clone(), Some(b"content")),
+            Ok(json::json!(true))
+        );
+        // the same upload is accepted again, a different one is not
+        assert_eq!(
+            call(&state, "consignment.post", params.clone(), Some(b"content")),
+            Ok(json::json!(false))
+        );
+        assert_eq!(
+            call(&state, "consignment.post", params, Some(b"other")),
+            Err(CANNOT_CHANGE_UPLOADED_FILE)
+        );
+
+        let consignment = call(
+            &state,
+            "consignment.get",
+            json::json!({ "recipient_id": "id" }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(consignment["consignment"], base64::encode(b"content"));
+        assert_eq!(consignment["txid"], "txid");
+        assert_eq!(consignment["vout"], 1);
+    }
+
+    #[test]
+    fn ack_post_and_get() {
+        let state = state("ack");
+        let recipient = json::json!({ "recipient_id": "id" });
+        let ack = json::json!({ "recipient_id": "id", "ack": true });
+        assert_eq!(
+            call(&state, "ack.get", recipient.clone(), None),
+            Err(NOT_FOUND_CONSIGNMENT)
+        );
+        assert_eq!(
+            call(&state, "ack.post", ack.clone(), None),
+            Err(NOT_FOUND_CONSIGNMENT)
+        );
+
+        let params = json::json!({ "recipient_id": "id", "txid": "txid" });
+        call(&state, "consignment.post", params, Some(b"content")).unwrap();
+        assert_eq!(
+            call(&state, "ack.get", recipient.clone(), None),
+            Ok(Value::Null)
+        );
+        assert_eq!(
+            call(&state, "ack.post", ack.clone(), None),
+            Ok(json::json!(true))
+        );
+        assert_eq!(
+            call(&state, "ack.get", recipient, None),
+            Ok(json::json!(true))
+        );
+        assert_eq!(call(&state, "ack.post", ack, None), Err(CANNOT_CHANGE_ACK));
+    }
+
+    #[test]
+    fn media_post_and_get() {
+        let state = state("media");
+        let params = json::json!({ "attachment_id": "id" });
+        assert_eq!(
+            call(&state, "media.get", params.clone(), None),
+            Err(NOT_FOUND_MEDIA)
+        );
+        assert_eq!(
+            call(&state, "media.post", params.clone(), Some(b"media")),
+            Ok(json::json!(true))
+        );
+        assert_eq!(
+            call(&state, "media.post", params.clone(), Some(b"other")),
+            Err(CANNOT_CHANGE_UPLOADED_FILE)
+        );
+        assert_eq!(
+            call(&state, "media.get", params, None),
+            Ok(json::json!(base64::encode(b"media")))
+        );
+    }
+
+    async fn response_body(response: Response<Body>) -> Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        json::from_slice(&body).unwrap()
+    }
+
+    fn post(content_type: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/json-rpc")
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body.to_owned()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn handle_json_request() {
+        let state = Arc::new(state("handle-json"));
+        let request = post(
+            "application/json",
+            r#"{"jsonrpc": "2.0", "id": 7, "method": "server.info"}"#,
+        );
+        let response = response_body(handle(state.clone(), request).await.unwrap()).await;
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["result"]["protocol_version"], PROTOCOL_VERSION);
+
+        let request = post("application/json", "not json");
+        let response = response_body(handle(state, request).await.unwrap()).await;
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn handle_multipart_request() {
+        let state = Arc::new(state("handle-multipart"));
+        let body = [
+            "--boundary",
+            "Content-Disposition: form-data; name=\"method\"",
+            "",
+            "consignment.post",
+            "--boundary",
+            "Content-Disposition: form-data; name=\"id\"",
+            "",
+            "1",
+            "--boundary",
+            "Content-Disposition: form-data; name=\"params\"",
+            "",
+            r#"{"recipient_id": "id", "txid": "txid", "vout": 0}"#,
+            "--boundary",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"consignment_out\"",
+            "Content-Type: application/octet-stream",
+            "",
+            "content",
+            "--boundary--",
+            "",
+        ]
+        .join("\r\n");
+        let request = post("multipart/form-data; boundary=boundary", &body);
+        let response = response_body(handle(state.clone(), request).await.unwrap()).await;
+        assert_eq!(response["id"], "1");
+        assert_eq!(response["result"], true, "{response}");
+
+        let stored = state.store.get_consignment("id").unwrap().unwrap();
+        assert_eq!(stored.consignment, base64::encode(b"content"));
+    }
+
+    #[tokio::test]
+    async fn handle_unknown_path() {
+        let state = Arc::new(state("handle-path"));
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/json-rpc")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle(state, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,247 @@
+//! On disk storage of the proxy
+//!
+//! Each record is a JSON file inside the data directory, e.g. the
+//! consignment for `<recipient_id>` is stored in `<root>/consignments/<recipient_id>`.
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Consignment {
+    /// The consignment file, encoded in base64
+    pub consignment: String,
+    pub txid: String,
+    pub vout: Option<u32>,
+    /// The receiver answer, `None` until the receiver validate the consignment.
+    pub ack: Option<bool>,
+}
+
+/// Result of [`FileStore::set_ack`].
+#[derive(Debug, PartialEq)]
+pub enum AckUpdate {
+    Updated,
+    /// The receiver already answered, the ack can not change.
+    AlreadySet(bool),
+    /// There is no consignment for the recipient.
+    NotFound,
+}
+
+pub struct FileStore {
+    root: PathBuf,
+    /// Serialize the writes, so a check-and-write is never
+    /// interleaved with another write.
+    lock: Mutex<()>,
+}
+
+impl FileStore {
+    pub fn new(root_dir: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(root_dir.join("consignments"))?;
+        fs::create_dir_all(root_dir.join("media"))?;
+        Ok(Self {
+            root: root_dir.to_path_buf(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn record_path(&self, kind: &str, id: &str) -> anyhow::Result<PathBuf> {
+        if id.is_empty() || id.contains('/') || id.contains('\\') || id.starts_with('.') {
+            anyhow::bail!("invalid id `{id}`");
+        }
+        Ok(self.root.join(kind).join(id))
+    }
+
+    fn read(&self, kind: &str, id: &str) -> anyhow::Result<Option<String>> {
+        let path = self.record_path(kind, id)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(path)?))
+    }
+
+    /// Write the value in a temporary file and then rename it, so
+    /// a crash never leaves a record half written.
+    fn write(&self, kind: &str, id: &str, value: &str) -> anyhow::Result<()> {
+        let path = self.record_path(kind, id)?;
+        let tmp_path = path.with_file_name(format!("{id}.tmp"));
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(value.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    pub fn get_consignment(&self, recipient_id: &str) -> anyhow::Result<Option<Consignment>> {
+        let _guard = self.lock.lock().unwrap();
+        let Some(value) = self.read("consignments", recipient_id)? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_str(&value)?))
+    }
+
+    /// Store the consignment if there is nothing for the recipient, and
+    /// return the one already stored otherwise.
+    pub fn put_consignment(
+        &self,
+        recipient_id: &str,
+        consignment: &Consignment,
+    ) -> anyhow::Result<Option<Consignment>> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(value) = self.read("consignments", recipient_id)? {
+            return Ok(Some(serde_json::from_str(&value)?));
+        }
+        self.write(
+            "consignments",
+            recipient_id,
+            &serde_json::to_string(consignment)?,
+        )?;
+        Ok(None)
+    }
+
+    /// Set the ack of the consignment, it can be set only once.
+    pub fn set_ack(&self, recipient_id: &str, ack: bool) -> anyhow::Result<AckUpdate> {
+        let _guard = self.lock.lock().unwrap();
+        let Some(value) = self.read("consignments", recipient_id)? else {
+            return Ok(AckUpdate::NotFound);
+        };
+        let mut consignment: Consignment = serde_json::from_str(&value)?;
+        if let Some(ack) = consignment.ack {
+            return Ok(AckUpdate::AlreadySet(ack));
+        }
+        consignment.ack = Some(ack);
+        self.write(
+            "consignments",
+            recipient_id,
+            &serde_json::to_string(&consignment)?,
+        )?;
+        Ok(AckUpdate::Updated)
+    }
+
+    /// Return the media encoded in base64.
+    pub fn get_media(&self, attachment_id: &str) -> anyhow::Result<Option<String>> {
+        let _guard = self.lock.lock().unwrap();
+        self.read("media", attachment_id)
+    }
+
+    /// Store the media if there is nothing for the attachment, and
+    /// return the one already stored otherwise.
+    pub fn put_media(&self, attachment_id: &str, media: &str) -> anyhow::Result<Option<String>> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(value) = self.read("media", attachment_id)? {
+            return Ok(Some(value));
+        }
+        self.write("media", attachment_id, media)?;
+        Ok(None)
+    }
+}
+
+/// A store inside a new temporary directory.
+#[cfg(test)]
+pub(crate) fn temp_store(name: &str) -> FileStore {
+    let dir = std::env::temp_dir().join(format!("rgb-proxy-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    FileStore::new(&dir).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn consignment(content: &str) -> Consignment {
+        Consignment {
+            consignment: content.to_owned(),
+            txid: "txid".to_owned(),
+            vout: Some(0),
+            ack: None,
+        }
+    }
+
+    #[test]
+    fn consignment_is_stored_once() {
+        let store = temp_store("consignment-once");
+        assert!(store.get_consignment("recipient").unwrap().is_none());
+
+        assert!(store
+            .put_consignment("recipient", &consignment("first"))
+            .unwrap()
+            .is_none());
+        let stored = store
+            .put_consignment("recipient", &consignment("second"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.consignment, "first");
+
+        let stored = store.get_consignment("recipient").unwrap().unwrap();
+        assert_eq!(stored.consignment, "first");
+        assert_eq!(stored.txid, "txid");
+        assert_eq!(stored.vout, Some(0));
+        assert_eq!(stored.ack, None);
+    }
+
+    #[test]
+    fn ack_is_set_once() {
+        let store = temp_store("ack-once");
+        assert_eq!(
+            store.set_ack("recipient", true).unwrap(),
+            AckUpdate::NotFound
+        );
+
+        store
+            .put_consignment("recipient", &consignment("content"))
+            .unwrap();
+        assert_eq!(
+            store.set_ack("recipient", true).unwrap(),
+            AckUpdate::Updated
+        );
+        assert_eq!(
+            store.set_ack("recipient", false).unwrap(),
+            AckUpdate::AlreadySet(true)
+        );
+        let stored = store.get_consignment("recipient").unwrap().unwrap();
+        assert_eq!(stored.ack, Some(true));
+    }
+
+    #[test]
+    fn media_is_stored_once() {
+        let store = temp_store("media-once");
+        assert!(store.get_media("attachment").unwrap().is_none());
+        assert!(store.put_media("attachment", "first").unwrap().is_none());
+        assert_eq!(
+            store.put_media("attachment", "second").unwrap().as_deref(),
+            Some("first")
+        );
+        assert_eq!(
+            store.get_media("attachment").unwrap().as_deref(),
+            Some("first")
+        );
+    }
+
+    #[test]
+    fn invalid_ids_are_refused() {
+        let store = temp_store("invalid-ids");
+        for id in ["", ".", "..", "../escape", "a/b", "a\\b", ".hidden"] {
+            assert!(store.get_media(id).is_err(), "`{id}` is accepted");
+            assert!(store.put_media(id, "media").is_err(), "`{id}` is accepted");
+            assert!(
+                store.put_consignment(id, &consignment("content")).is_err(),
+                "`{id}` is accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn write_does_not_leave_temporary_files() {
+        let store = temp_store("no-tmp");
+        store
+            .put_consignment("recipient", &consignment("content"))
+            .unwrap();
+        store.set_ack("recipient", true).unwrap();
+        let files = fs::read_dir(store.root.join("consignments"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["recipient".to_owned()]);
+    }
+}
@@ -15,9 +15,9 @@ mod tests {
 
     use clightning_testing::cln;
 
-    use crate::node;
     #[allow(unused_imports)]
     use crate::utils::*;
+    use crate::{check, node, wait, wait_sync};
 
     static INIT: Once = Once::new();
 
@@ -25,6 +25,7 @@ mod tests {
         // ignore error
         INIT.call_once(|| {
             env_logger::init();
+            start_rgb_proxy();
         });
     }
 
@@ -55,26 +56,72 @@ mod tests {
 
         ocean_ln.print_logs()?;
 
+        // the fundee accepted the funding consignment, and the
+        // funder sees the ACK after a refresh
+        let _: Value = miner_1.rpc().call("rgbrefresh", json::json!({}))?;
+        let funder_channels: Value = miner_1.rpc().call("rgblistchannels", json::json!({}))?;
+        let funder_channels = funder_channels["channels"].as_array().unwrap();
+        assert_eq!(funder_channels.len(), 1, "{:?}", funder_channels);
+        assert_eq!(funder_channels[0]["funding_ack"], true);
+        let fundee_channels: Value = ocean_ln.rpc().call("rgblistchannels", json::json!({}))?;
+        let fundee_channels = fundee_channels["channels"].as_array().unwrap();
+        assert_eq!(fundee_channels.len(), 1, "{:?}", fundee_channels);
+        assert_eq!(
+            fundee_channels[0]["channel_id"],
+            funder_channels[0]["channel_id"]
+        );
+        assert_eq!(fundee_channels[0]["status"], "confirmed");
+        assert_eq!(fundee_channels[0]["funding_ack"], true);
+        assert_eq!(fundee_channels[0]["local_rgb_amount"], 0);
+        assert_eq!(fundee_channels[0]["remote_rgb_amount"], 1000);
+
         #[derive(Deserialize, Debug)]
         struct Invoice {
             bolt11: String,
         }
 
-        // the miner generate the payout reusable offer
-        let payout_miner: Invoice = miner_1.rpc().call(
-            "invoice",
+        #[derive(Deserialize, Debug)]
+        struct RgbInvoice {
+            invoice: Invoice,
+        }
+
+        // FIXME: we are not able at the moment to splice the channel to increase the balance,
+        // so at the moment, so atm we open a new channel but this is not inside our simulation
+        let asset_id = open_rgb_channel(&ocean_ln, &miner_1, false)?;
+
+        let rgb_channels: Value = ocean_ln
+            .rpc()
+            .call("rgblistchannels", json::json!({ "asset_id": asset_id }))?;
+        log::info!("rgb channels: {rgb_channels}");
+        let rgb_channels = rgb_channels["channels"].as_array().unwrap();
+        assert_eq!(rgb_channels.len(), 1, "{:?}", rgb_channels);
+        assert_eq!(rgb_channels[0]["contract_id"], asset_id.as_str());
+        assert_eq!(rgb_channels[0]["peer_id"], miner_1.rpc().getinfo()?.id);
+        // the payee is the fundee of the channel, and it needs to know
+        // the short channel id to check the incoming htlc
+        let fundee_channels: Value = miner_1
+            .rpc()
+            .call("rgblistchannels", json::json!({ "asset_id": asset_id }))?;
+        let fundee_channel = &fundee_channels["channels"][0];
+        assert_eq!(fundee_channel["status"], "confirmed", "{fundee_channels}");
+        assert_eq!(
+            fundee_channel["short_channel_id"], rgb_channels[0]["short_channel_id"],
+            "{fundee_channels}"
+        );
+
+        // the miner generate the payout invoice in the asset of the channel
+        let payout_miner: RgbInvoice = miner_1.rpc().call(
+            "rgbinvoice",
             json::json!({
-                "amount_msat": "any",
+                "amount_msat": "10sat",
                 "label": "invoice",
                 "description": "invoice1",
-
+                "asset_id": asset_id,
+                "asset_amount": 10,
             }),
         )?;
 
-        log::info!("offer invoice: {:?}", payout_miner);
-        // FIXME: we are not able at the moment to splice the channel to increase the balance,
-        // so at the moment, so atm we open a new channel but this is not inside our simulation
-        open_rgb_channel(&ocean_ln, &miner_1, false)?;
+        log::info!("rgb invoice: {:?}", payout_miner);
 
         let listchannels = ocean_ln.rpc().listchannels(None, None, None)?.channels;
         log::debug!(
@@ -86,14 +133,136 @@ mod tests {
             "channels in list funds before paying: {}",
             json::to_string(&listchannels)?
         );
-        let payout: Value = ocean_ln.rpc().call(
-            "pay",
+        let payout = ocean_ln.rpc().call::<Value, Value>(
+            "rgbpay",
             json::json!({
-                "bolt11": payout_miner.bolt11,
-                "amount_msat": "10sat",
+                "bolt11": payout_miner.invoice.bolt11,
             }),
+        );
+        check!(ocean_ln, payout, "rgbpay fails");
+        let payout = payout?;
+        log::info!("payment started: {payout}");
+        let payment: Value = ocean_ln.rpc().call(
+            "waitsendpay",
+            json::json!({ "payment_hash": payout["sendpay"]["payment_hash"] }),
         )?;
-        log::info!("payment result: {payout}");
+        assert_eq!(payment["status"], "complete", "{payment}");
+
+        // the rgb amount moved on both sides of the channel, the
+        // notifications that settle the htlc are handled in background
+        let rgb_channel = |node: &cln::Node| -> anyhow::Result<Value> {
+            let channels: Value = node
+                .rpc()
+                .call("rgblistchannels", json::json!({ "asset_id": asset_id }))?;
+            Ok(channels["channels"][0].clone())
+        };
+        wait!(
+            || {
+                let payer = rgb_channel(&ocean_ln).map_err(|_| ())?;
+                let payee = rgb_channel(&miner_1).map_err(|_| ())?;
+                if payer["local_rgb_amount"] != 990 || payee["local_rgb_amount"] != 10 {
+                    return Err(());
+                }
+                Ok(())
+            },
+            1000
+        );
+        let payer_channel = rgb_channel(&ocean_ln)?;
+        assert_eq!(payer_channel["local_rgb_amount"], 990, "{payer_channel}");
+        assert_eq!(payer_channel["remote_rgb_amount"], 10, "{payer_channel}");
+        let payee_channel = rgb_channel(&miner_1)?;
+        assert_eq!(payee_channel["local_rgb_amount"], 10, "{payee_channel}");
+        assert_eq!(payee_channel["remote_rgb_amount"], 990, "{payee_channel}");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ntest::timeout(560000)]
+    async fn test_open_rgb_channel_with_datastore() -> anyhow::Result<()> {
+        init();
+
+        let funder = node!(params: "--rgb-storage=datastore");
+        let btc = funder.btc();
+        let fundee = node!(btc.clone(), "--rgb-storage=datastore");
+        if let Err(err) = open_rgb_channel(&funder, &fundee, false) {
+            funder.print_logs()?;
+            panic!("{err}");
+        }
+
+        // the channel info lives inside the lightningd datastore
+        let datastore: Value = funder
+            .rpc()
+            .call("listdatastore", json::json!({ "key": ["rgb", "channel"] }))?;
+        log::info!("datastore: {datastore}");
+        let channels = datastore["datastore"].as_array().unwrap();
+        assert_eq!(channels.len(), 1, "{datastore}");
+        let info: Value = json::from_str(channels[0]["string"].as_str().unwrap())?;
+
+        let rgb_channels: Value = funder.rpc().call("rgblistchannels", json::json!({}))?;
+        let rgb_channels = rgb_channels["channels"].as_array().unwrap();
+        assert_eq!(rgb_channels.len(), 1, "{:?}", rgb_channels);
+        assert_eq!(rgb_channels[0]["channel_id"], info["channel_id"]);
+        assert_eq!(rgb_channels[0]["local_rgb_amount"], 1000);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ntest::timeout(560000)]
+    async fn test_rgb_send_batch() -> anyhow::Result<()> {
+        init();
+
+        let sender = node!();
+        let btc = sender.btc();
+        let receiver = node!(btc.clone());
+        for node in [&sender, &receiver] {
+            let addr = node.rpc().newaddr(None)?.bech32.unwrap();
+            fund_wallet(node.btc(), &addr, 8)?;
+            wait_for_funds(node)?;
+            wait_sync!(node);
+        }
+
+        let asset_id = make_new_asset_id(&sender, "USDT".to_owned(), "Tether".to_owned())?;
+        let assets: Value = sender.rpc().call("rgblistassets", json::json!({}))?;
+        log::info!("rgb assets: {assets}");
+        assert!(assets["assets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|asset| asset["asset_id"] == asset_id.as_str()));
+        // the asset can be referenced by its name and ticker
+        let balance: Value = sender
+            .rpc()
+            .call("rgbbalances", json::json!({ "asset_id": "Tether/USDT" }))?;
+        assert_eq!(balance["assets"]["settled"], 10000, "{balance}");
+        let mut recipients = vec![];
+        for amount in [10, 20] {
+            let receive: Value = receiver
+                .rpc()
+                .call("rgbreceive", json::json!({ "asset_id": asset_id }))?;
+            log::info!("rgb receive: {receive}");
+            // pay one recipient with the raw data, and the other with the invoice
+            if amount == 10 {
+                recipients.push(json::json!({
+                    "asset_id": "USDT",
+                    "amount": amount,
+                    "blinded_utxo": receive["recipient_id"],
+                }));
+            } else {
+                recipients.push(json::json!({
+                    "invoice": receive["invoice"],
+                    "amount": amount,
+                }));
+            }
+        }
+
+        let send = sender.rpc().call::<Value, Value>(
+            "rgbsendasset",
+            json::json!({
+                "recipients": recipients,
+            }),
+        );
+        check!(sender, send, "rgbsendasset with many recipients fails");
+        log::info!("rgb send: {:?}", send);
         Ok(())
     }
 }
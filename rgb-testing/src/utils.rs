@@ -28,27 +28,33 @@ macro_rules! wait {
 
 #[macro_export]
 macro_rules! node {
-    ($btc:expr) => {{
+    ($btc:expr, $params:expr) => {{
         let pwd = std::env!("PWD");
         let plugin_name = std::env!("PLUGIN_NAME");
         log::debug!("plugin path: {pwd}/../{plugin_name}");
         cln::Node::with_btc_and_params(
             $btc,
-            &format!("--developer --experimental-offers --plugin={pwd}/target/debug/{plugin_name}"),
+            &format!("--developer --experimental-offers --plugin={pwd}/target/debug/{plugin_name} --rgb-allow-issuance=true {}", $params),
             "regtest",
         )
         .await?
     }};
-    () => {{
+    (params: $params:expr) => {{
         let pwd = std::env!("PWD");
         let plugin_name = std::env!("PLUGIN_NAME");
         log::debug!("plugin path: {pwd}/../{plugin_name}");
         cln::Node::with_params(
-            &format!("--developer --experimental-offers --plugin={pwd}/target/debug/{plugin_name}"),
+            &format!("--developer --experimental-offers --plugin={pwd}/target/debug/{plugin_name} --rgb-allow-issuance=true {}", $params),
             "regtest",
         )
         .await?
     }};
+    ($btc:expr) => {
+        $crate::node!($btc, "")
+    };
+    () => {
+        $crate::node!(params: "")
+    };
 }
 
 #[macro_export]
@@ -93,6 +99,18 @@ macro_rules! wait_sync {
     }};
 }
 
+/// Start the RGB proxy where the plugin looks for it on regtest.
+///
+/// If the address is already in use we assume that the developer
+/// is running a proxy, and we use it.
+pub fn start_rgb_proxy() {
+    let addr = rgb_proxy::DEFAULT_ADDR.parse().unwrap();
+    let data_dir = std::env::temp_dir().join(format!("rgb-proxy-{}", std::process::id()));
+    if let Err(err) = rgb_proxy::spawn(addr, &data_dir) {
+        log::warn!("rgb proxy not started, using the one at `{addr}`: {err}");
+    }
+}
+
 pub fn make_new_asset_id(node: &cln::Node, ticker: String, name: String) -> anyhow::Result<String> {
     let asset: serde_json::Value = node.rpc().call(
         "issueasset",
@@ -104,19 +122,22 @@ pub fn make_new_asset_id(node: &cln::Node, ticker: String, name: String) -> anyh
         }),
     )?;
     log::info!("new asset generated is `{asset}`");
-    let asset_id = asset.get("asset_id").unwrap();
-    Ok(asset_id.to_string())
+    let asset_id = asset.get("asset_id").and_then(|id| id.as_str()).unwrap();
+    Ok(asset_id.to_owned())
 }
 
-/// Open a channel from node_a -> node_b
+/// Open a channel from node_a -> node_b and return the asset id
+/// of the channel.
 pub fn open_rgb_channel(
     node_a: &cln::Node,
     node_b: &cln::Node,
     dual_open: bool,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<String> {
     let addr = node_a.rpc().newaddr(None)?.bech32.unwrap();
     fund_wallet(node_a.btc(), &addr, 8)?;
     wait_for_funds(node_a)?;
+    // the funding transaction is paid by the RGB wallet, not by cln
+    fund_rgb_wallet(node_a)?;
 
     wait_sync!(node_a);
 
@@ -130,8 +151,9 @@ pub fn open_rgb_channel(
         "fundrgbchannel",
         serde_json::json!({
             "peer_id": getinfo2.id,
-            "amount_msat": "all",
+            "amount_sat": "all",
             "asset_id": asset_id,
+            "asset_amount": 1000,
         }),
     )?;
     wait!(
@@ -150,7 +172,7 @@ pub fn open_rgb_channel(
         },
         10000
     );
-    Ok(())
+    Ok(asset_id)
 }
 
 pub fn fund_wallet(btc: Arc<btc::BtcNode>, addr: &str, blocks: u64) -> anyhow::Result<String> {
@@ -165,6 +187,38 @@ pub fn fund_wallet(btc: Arc<btc::BtcNode>, addr: &str, blocks: u64) -> anyhow::R
     Ok(address.to_string())
 }
 
+/// Send some bitcoin to the RGB wallet of the node, and wait
+/// until the wallet is able to spend them.
+pub fn fund_rgb_wallet(cln: &cln::Node) -> anyhow::Result<()> {
+    let addr: serde_json::Value = cln.rpc().call("rgbnewaddr", json!({}))?;
+    let addr = addr["address"].as_str().unwrap();
+    fund_wallet(cln.btc(), addr, 1)?;
+    // the coinbase is spendable only after 100 blocks
+    let cln_addr = cln.rpc().newaddr(None)?.bech32.unwrap();
+    fund_wallet(cln.btc(), &cln_addr, 100)?;
+
+    wait!(
+        || {
+            let Ok(balance) = cln
+                .rpc()
+                .call::<_, serde_json::Value>("rgbbalances", json!({}))
+            else {
+                return Err(());
+            };
+            log::trace!("rgbbalances {balance}");
+            let spendable = balance["onchain"]["rgb"]["vanilla"]["spendable"]
+                .as_u64()
+                .unwrap_or_default();
+            if spendable == 0 {
+                return Err(());
+            }
+            Ok(())
+        },
+        10000
+    );
+    Ok(())
+}
+
 pub fn wait_for_funds(cln: &cln::Node) -> anyhow::Result<()> {
     use clightning_testing::prelude::bitcoincore_rpc;
     use clightning_testing::prelude::bitcoincore_rpc::RpcApi;